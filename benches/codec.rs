@@ -0,0 +1,79 @@
+//! Encode and decode throughput over the generated corpus
+//!
+//! Throughput is reported against the raw image size, so criterion's MB/s figures are comparable
+//! between images. Compression ratios are printed once per image before it is measured.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use qoi_rs::{decode, encode, ColorSpace};
+
+mod common;
+
+/// Dimensions of the generated images, from icons up to full HD
+const SIZES: [(u32, u32); 3] = [(64, 64), (512, 512), (1920, 1080)];
+
+/// Benchmarks encoding and decoding `pixels` of `image` with `$channels` channels
+macro_rules! bench_channels {
+    ($c:expr, $image:expr, $pixels:expr, $channels:literal) => {{
+        let image = $image;
+        let pixels = $pixels;
+        let id = format!("{}/{}x{}", image.name, image.width, image.height);
+
+        let mut buf = vec![];
+        encode(
+            &mut buf,
+            &pixels,
+            image.width,
+            image.height,
+            ColorSpace::Srgb,
+        )
+        .unwrap();
+
+        let raw_size = image.raw_size($channels);
+        println!(
+            "{id} ({} channels): {raw_size} -> {} bytes, ratio {:.3}",
+            $channels,
+            buf.len(),
+            buf.len() as f64 / raw_size as f64
+        );
+
+        let mut group = $c.benchmark_group(concat!("channels_", $channels));
+        group.throughput(Throughput::Bytes(raw_size as u64));
+
+        let mut output = Vec::with_capacity(buf.len());
+        group.bench_with_input(BenchmarkId::new("encode", &id), &pixels, |b, pixels| {
+            b.iter(|| {
+                output.clear();
+                encode(
+                    &mut output,
+                    black_box(pixels),
+                    image.width,
+                    image.height,
+                    ColorSpace::Srgb,
+                )
+                .unwrap()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("decode", &id), &buf, |b, buf| {
+            b.iter(|| decode::<$channels>(&mut black_box(buf.as_slice())).unwrap())
+        });
+
+        group.finish();
+    }};
+}
+
+fn codec(c: &mut Criterion) {
+    for (width, height) in SIZES {
+        for image in common::corpus(width, height) {
+            bench_channels!(c, &image, image.rgb(), 3);
+            bench_channels!(c, &image, image.rgba(), 4);
+        }
+    }
+}
+
+criterion_group! {
+    name = benches;
+    // NB: The corpus is large, so fewer samples keep a full run to a few minutes
+    config = Criterion::default().sample_size(20);
+    targets = codec
+}
+criterion_main!(benches);
@@ -0,0 +1,211 @@
+//! Deterministic image corpus shared by the benchmarks
+//!
+//! Every image is generated from its dimensions alone, so results are comparable between runs
+//! and machines without checking in any image files.
+
+// NB: Not every benchmark uses every part of the corpus
+#![allow(dead_code)]
+
+use qoi_rs::Pixel;
+
+/// A generated test image with straight RGBA channels
+pub struct Image {
+    pub name: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<[u8; 4]>,
+}
+
+impl Image {
+    fn generate(
+        name: &'static str,
+        width: u32,
+        height: u32,
+        mut pixel: impl FnMut(u32, u32) -> [u8; 4],
+    ) -> Self {
+        let rgba = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| pixel(x, y))
+            .collect();
+
+        Self {
+            name,
+            width,
+            height,
+            rgba,
+        }
+    }
+
+    /// Size of the image in bytes with `channels` per pixel
+    pub fn raw_size(&self, channels: usize) -> usize {
+        self.rgba.len() * channels
+    }
+
+    pub fn rgb(&self) -> Vec<Pixel<3>> {
+        self.rgba
+            .iter()
+            .map(|&[r, g, b, _]| Pixel::rgb(r, g, b))
+            .collect()
+    }
+
+    pub fn rgba(&self) -> Vec<Pixel<4>> {
+        self.rgba
+            .iter()
+            .map(|&[r, g, b, a]| Pixel::rgba(r, g, b, a))
+            .collect()
+    }
+}
+
+/// A deterministic pseudo-random number generator
+pub fn lcg(seed: u32) -> impl FnMut() -> u32 {
+    let mut state = seed;
+    move || {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        state >> 16
+    }
+}
+
+/// Uniform random noise on every colour channel, the worst case for QOI
+pub fn noise(width: u32, height: u32) -> Image {
+    let mut random = lcg(1);
+    Image::generate("noise", width, height, |_, _| {
+        let [r, g, b, _] = random().to_le_bytes();
+        let [_, _, a, _] = random().to_le_bytes();
+        [r, g, b, a | 0x80]
+    })
+}
+
+/// Smooth shading with sensor-like noise on every channel
+pub fn photo(width: u32, height: u32) -> Image {
+    let mut random = lcg(2);
+    Image::generate("photo", width, height, |x, y| {
+        let mut channel = |base: u32| (base + random() % 9) as u8;
+        [
+            channel(x * 200 / width),
+            channel(y * 200 / height),
+            channel((x + y) * 100 / (width + height) + 50),
+            255,
+        ]
+    })
+}
+
+/// Linear gradients across the whole image
+pub fn gradient(width: u32, height: u32) -> Image {
+    Image::generate("gradient", width, height, |x, y| {
+        [
+            (x * 256 / width) as u8,
+            (y * 256 / height) as u8,
+            ((x + y) * 128 / (width + height)) as u8,
+            255,
+        ]
+    })
+}
+
+/// Flat panels with borders and dense runs of "text" in a few colours, like a UI screenshot
+pub fn screenshot(width: u32, height: u32) -> Image {
+    let mut random = lcg(3);
+    Image::generate("screenshot", width, height, |x, y| {
+        if x % 256 < 2 || y % 128 < 2 {
+            [20, 20, 20, 255]
+        } else if y % 24 < 12 && x % 300 > 20 && random().is_multiple_of(3) {
+            [30, 30, 30, 255]
+        } else if x < width / 5 {
+            [45, 50, 60, 255]
+        } else {
+            [250, 250, 250, 255]
+        }
+    })
+}
+
+/// Anti-aliased circular sprites on a fully transparent background
+pub fn sprites(width: u32, height: u32) -> Image {
+    const SPRITE: u32 = 32;
+
+    Image::generate("sprites", width, height, |x, y| {
+        let (cell_x, cell_y) = (x / SPRITE, y / SPRITE);
+        let (dx, dy) = (
+            (x % SPRITE) as i32 - SPRITE as i32 / 2,
+            (y % SPRITE) as i32 - SPRITE as i32 / 2,
+        );
+
+        // NB: Alpha fades out over the outer quarter of the radius
+        let radius = (SPRITE / 2 - 2) as i32;
+        let distance = dx * dx + dy * dy;
+        let alpha = (radius * radius - distance).clamp(0, radius * radius / 4) * 255
+            / (radius * radius / 4);
+
+        if alpha == 0 {
+            [0, 0, 0, 0]
+        } else {
+            [
+                (cell_x * 50) as u8,
+                (cell_y * 70) as u8,
+                (128 + dx * 4) as u8,
+                alpha as u8,
+            ]
+        }
+    })
+}
+
+/// Every kind of image in the corpus at `width` and `height`
+pub fn corpus(width: u32, height: u32) -> Vec<Image> {
+    vec![
+        noise(width, height),
+        photo(width, height),
+        gradient(width, height),
+        screenshot(width, height),
+        sprites(width, height),
+    ]
+}
+
+/// Frames of a screen recording: text is typed into a `screenshot` one glyph per frame while a
+/// cursor moves around, and a window is dragged across every few frames
+pub fn screen_recording(width: u32, height: u32, count: u32) -> Vec<Image> {
+    const GLYPH: u32 = 8;
+
+    let background = screenshot(width, height);
+    let mut screen = background.rgba.clone();
+
+    (0..count)
+        .map(|i| {
+            // Type a glyph
+            let columns = (width / GLYPH).max(1);
+            let (glyph_x, glyph_y) = ((i % columns) * GLYPH, (i / columns * 2 * GLYPH) % height);
+            for y in glyph_y..(glyph_y + GLYPH).min(height) {
+                for x in glyph_x..(glyph_x + GLYPH).min(width) {
+                    if (x * 7 + y * 3 + i) % 5 < 2 {
+                        screen[(y * width + x) as usize] = [30, 30, 30, 255];
+                    }
+                }
+            }
+
+            let mut frame = screen.clone();
+
+            // Draw a window, which moves every 4 frames
+            let (window_x, window_y) = ((i / 4 * 16) % (width / 2), height / 4);
+            for y in window_y..(window_y + height / 3).min(height) {
+                for x in window_x..(window_x + width / 3).min(width) {
+                    frame[(y * width + x) as usize] = match y - window_y < 20 {
+                        true => [60, 90, 200, 255],
+                        false => [255, 255, 255, 255],
+                    };
+                }
+            }
+
+            // Draw the cursor
+            let (cursor_x, cursor_y) = ((i * 37) % width, (i * 23) % height);
+            for y in cursor_y..(cursor_y + 12).min(height) {
+                for x in cursor_x..(cursor_x + (y - cursor_y) / 2).min(width) {
+                    frame[(y * width + x) as usize] = [0, 0, 0, 255];
+                }
+            }
+
+            Image {
+                name: "screen_recording",
+                width,
+                height,
+                rgba: frame,
+            }
+        })
+        .collect()
+}
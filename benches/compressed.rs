@@ -0,0 +1,112 @@
+//! Plain QOI against QOI wrapped in LZ4 and Zstandard over the generated corpus
+//!
+//! Sizes of every variant are printed before each image is measured, so the extra compression can
+//! be weighed against the throughput lost to it.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use qoi_rs::{
+    compressed::{self, Compression},
+    encode, ColorSpace,
+};
+
+mod common;
+
+const SIZES: [(u32, u32); 2] = [(512, 512), (1920, 1080)];
+
+const COMPRESSIONS: [(&str, Compression); 3] = [
+    ("lz4", Compression::Lz4),
+    ("zstd-1", Compression::Zstd(1)),
+    ("zstd-9", Compression::Zstd(9)),
+];
+
+fn compressions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compressed");
+
+    for (width, height) in SIZES {
+        for image in common::corpus(width, height) {
+            let pixels = image.rgba();
+            let id = format!("{}/{width}x{height}", image.name);
+
+            let mut plain = vec![];
+            encode(&mut plain, &pixels, width, height, ColorSpace::Srgb).unwrap();
+
+            let mut sizes = format!("{id}: raw {}, qoi {}", image.raw_size(4), plain.len());
+            let mut encoded = vec![];
+            for (name, compression) in COMPRESSIONS {
+                let mut buf = vec![];
+                compressed::encode(
+                    &mut buf,
+                    &pixels,
+                    width,
+                    height,
+                    ColorSpace::Srgb,
+                    compression,
+                )
+                .unwrap();
+
+                sizes += &format!(
+                    ", {name} {} ({:.1}% of qoi)",
+                    buf.len(),
+                    buf.len() as f64 * 100.0 / plain.len() as f64
+                );
+                encoded.push((name, compression, buf));
+            }
+            println!("{sizes}");
+
+            group.throughput(Throughput::Bytes(image.raw_size(4) as u64));
+            group.bench_with_input(BenchmarkId::new("encode/qoi", &id), &pixels, |b, pixels| {
+                b.iter(|| {
+                    let mut output = Vec::with_capacity(plain.len());
+                    encode(
+                        &mut output,
+                        black_box(pixels),
+                        width,
+                        height,
+                        ColorSpace::Srgb,
+                    )
+                    .unwrap()
+                })
+            });
+            group.bench_with_input(BenchmarkId::new("decode/qoi", &id), &plain, |b, buf| {
+                b.iter(|| compressed::decode::<4>(&mut black_box(buf.as_slice())).unwrap())
+            });
+
+            for (name, compression, buf) in &encoded {
+                group.bench_with_input(
+                    BenchmarkId::new(format!("encode/{name}"), &id),
+                    &pixels,
+                    |b, pixels| {
+                        b.iter(|| {
+                            let mut output = Vec::with_capacity(buf.len());
+                            compressed::encode(
+                                &mut output,
+                                black_box(pixels),
+                                width,
+                                height,
+                                ColorSpace::Srgb,
+                                *compression,
+                            )
+                            .unwrap()
+                        })
+                    },
+                );
+                group.bench_with_input(
+                    BenchmarkId::new(format!("decode/{name}"), &id),
+                    buf,
+                    |b, buf| {
+                        b.iter(|| compressed::decode::<4>(&mut black_box(buf.as_slice())).unwrap())
+                    },
+                );
+            }
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = compressions
+}
+criterion_main!(benches);
@@ -0,0 +1,33 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use qoi_rs::{decode, decode_from_slice, encode, ColorSpace};
+
+mod common;
+
+const WIDTH: u32 = 1024;
+const HEIGHT: u32 = 768;
+
+fn decoders(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+
+    for image in [
+        common::photo(WIDTH, HEIGHT),
+        common::screenshot(WIDTH, HEIGHT),
+        common::gradient(WIDTH, HEIGHT),
+    ] {
+        let mut buf = vec![];
+        encode(&mut buf, &image.rgba(), WIDTH, HEIGHT, ColorSpace::Srgb).unwrap();
+
+        group.throughput(Throughput::Bytes(image.raw_size(4) as u64));
+        group.bench_with_input(BenchmarkId::new("naive", image.name), &buf, |b, buf| {
+            b.iter(|| decode::<4>(&mut black_box(buf.as_slice())).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("slice", image.name), &buf, |b, buf| {
+            b.iter(|| decode_from_slice::<4>(black_box(buf)).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, decoders);
+criterion_main!(benches);
@@ -0,0 +1,46 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use qoi_rs::{encode, ColorSpace, Pixel};
+
+const WIDTH: u32 = 1024;
+const HEIGHT: u32 = 1024;
+
+/// A single colour image, which encodes entirely into `QOI_OP_RUN`s
+fn flat() -> Vec<Pixel<4>> {
+    vec![Pixel::rgba(30, 60, 90, 255); (WIDTH * HEIGHT) as usize]
+}
+
+/// Large flat rectangles separated by thin borders, like a UI screenshot
+fn panels() -> Vec<Pixel<4>> {
+    (0..WIDTH * HEIGHT)
+        .map(|i| {
+            let (x, y) = (i % WIDTH, i / WIDTH);
+            if x % 256 < 2 || y % 128 < 2 {
+                Pixel::rgba(20, 20, 20, 255)
+            } else {
+                let shade = (x / 256 * 40 + y / 128 * 10) as u8;
+                Pixel::rgba(200, shade, 255 - shade, 255)
+            }
+        })
+        .collect()
+}
+
+fn run_detection(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode_runs");
+
+    for (name, pixels) in [("flat", flat()), ("panels", panels())] {
+        let mut buf = Vec::with_capacity(pixels.len() * 5);
+
+        group.throughput(Throughput::Bytes((pixels.len() * 4) as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &pixels, |b, pixels| {
+            b.iter(|| {
+                buf.clear();
+                encode(&mut buf, black_box(pixels), WIDTH, HEIGHT, ColorSpace::Srgb).unwrap()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, run_detection);
+criterion_main!(benches);
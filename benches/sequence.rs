@@ -0,0 +1,67 @@
+//! Encoding screen recordings as sequences of delta frames against independent frames
+//!
+//! The total size of both is printed before each recording is measured.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use qoi_rs::{encode, sequence, ColorSpace};
+
+mod common;
+
+const SIZES: [(u32, u32); 2] = [(640, 480), (1920, 1080)];
+
+const FRAMES: u32 = 30;
+
+fn sequences(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sequence");
+
+    for (width, height) in SIZES {
+        let frames: Vec<_> = common::screen_recording(width, height, FRAMES)
+            .iter()
+            .map(common::Image::rgba)
+            .collect();
+        let id = format!("{width}x{height}");
+        let raw_size = frames.len() * (width * height) as usize * 4;
+
+        let mut independent = 0;
+        for frame in &frames {
+            independent += encode(&mut vec![], frame, width, height, ColorSpace::Srgb).unwrap();
+        }
+
+        let mut buf = vec![];
+        sequence::encode(&mut buf, &frames, width, height, ColorSpace::Srgb).unwrap();
+
+        println!(
+            "{id} x {FRAMES}: raw {raw_size}, independent frames {independent}, sequence {} \
+             ({:.1}% of independent)",
+            buf.len(),
+            buf.len() as f64 * 100.0 / independent as f64
+        );
+
+        group.throughput(Throughput::Bytes(raw_size as u64));
+        group.bench_with_input(BenchmarkId::new("encode", &id), &frames, |b, frames| {
+            b.iter(|| {
+                let mut output = Vec::with_capacity(buf.len());
+                sequence::encode(
+                    &mut output,
+                    black_box(frames),
+                    width,
+                    height,
+                    ColorSpace::Srgb,
+                )
+                .unwrap()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("decode", &id), &buf, |b, buf| {
+            b.iter(|| sequence::decode::<4>(&mut black_box(buf.as_slice())).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = sequences
+}
+criterion_main!(benches);
@@ -0,0 +1,91 @@
+//! Extended-profile images encoded with each colour transform over the generated corpus
+//!
+//! Sizes of every transform are printed before each image is measured, so the compression gained
+//! by decorrelating the channels can be weighed against the throughput lost to it.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use qoi_rs::{decode, encode_with_options, ColorSpace, EncodeOptions, Profile, Transform};
+
+mod common;
+
+const SIZES: [(u32, u32); 2] = [(512, 512), (1920, 1080)];
+
+const TRANSFORMS: [(&str, Transform); 3] = [
+    ("identity", Transform::Identity),
+    ("subtract-green", Transform::SubtractGreen),
+    ("ycocg-r", Transform::YCoCgR),
+];
+
+fn options(transform: Transform) -> EncodeOptions {
+    EncodeOptions {
+        profile: Profile::Extended,
+        transform,
+        ..Default::default()
+    }
+}
+
+fn transforms(c: &mut Criterion) {
+    let mut group = c.benchmark_group("transform");
+
+    for (width, height) in SIZES {
+        for image in common::corpus(width, height) {
+            let pixels = image.rgba();
+            let id = format!("{}/{width}x{height}", image.name);
+
+            let mut sizes = format!("{id}: raw {}", image.raw_size(4));
+            let mut encoded = vec![];
+            for (name, transform) in TRANSFORMS {
+                let mut buf = vec![];
+                encode_with_options(
+                    &mut buf,
+                    &pixels,
+                    width,
+                    height,
+                    ColorSpace::Srgb,
+                    &options(transform),
+                )
+                .unwrap();
+
+                sizes += &format!(", {name} {}", buf.len());
+                encoded.push((name, transform, buf));
+            }
+            println!("{sizes}");
+
+            group.throughput(Throughput::Bytes(image.raw_size(4) as u64));
+            for (name, transform, buf) in &encoded {
+                group.bench_with_input(
+                    BenchmarkId::new(format!("encode/{name}"), &id),
+                    &pixels,
+                    |b, pixels| {
+                        b.iter(|| {
+                            let mut output = Vec::with_capacity(buf.len());
+                            encode_with_options(
+                                &mut output,
+                                black_box(pixels),
+                                width,
+                                height,
+                                ColorSpace::Srgb,
+                                &options(*transform),
+                            )
+                            .unwrap()
+                        })
+                    },
+                );
+                group.bench_with_input(
+                    BenchmarkId::new(format!("decode/{name}"), &id),
+                    buf,
+                    |b, buf| b.iter(|| decode::<4>(&mut black_box(buf.as_slice())).unwrap()),
+                );
+            }
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = transforms
+}
+criterion_main!(benches);
@@ -54,6 +54,7 @@
 
         apps.qoi-rs = flake-utils.lib.mkApp {
           drv = packages.qoi-rs;
+          exePath = "/bin/qoi";
         };
         apps.default = apps.qoi-rs;
 
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qoi_rs::animation;
+
+fuzz_target!(|bytes: &[u8]| {
+    let _ = animation::decode(&mut &bytes[..]);
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qoi_rs::compressed;
+
+fuzz_target!(|bytes: &[u8]| {
+    let _ = compressed::decode::<3>(&mut &bytes[..]);
+    let _ = compressed::decode::<4>(&mut &bytes[..]);
+});
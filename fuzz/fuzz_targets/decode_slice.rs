@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qoi_rs::decode_from_slice;
+
+fuzz_target!(|bytes: &[u8]| {
+    let _ = decode_from_slice::<3>(bytes);
+    let _ = decode_from_slice::<4>(bytes);
+});
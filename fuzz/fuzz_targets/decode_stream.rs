@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qoi_rs::{decode, decode_with_options, AlphaMode, ColorSpace, DecodeOptions};
+
+fuzz_target!(|bytes: &[u8]| {
+    let _ = decode::<3>(&mut &bytes[..]);
+
+    let options = DecodeOptions {
+        alpha: AlphaMode::Premultiplied,
+        color_space: Some(ColorSpace::AllLinear),
+        ..Default::default()
+    };
+    let _ = decode_with_options::<4>(&mut &bytes[..], &options);
+});
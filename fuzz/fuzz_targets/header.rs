@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qoi_rs::Header;
+
+fuzz_target!(|bytes: [u8; Header::SIZE]| {
+    let _ = Header::from_bytes(bytes);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qoi_rs::metadata;
+
+fuzz_target!(|bytes: &[u8]| {
+    let _ = metadata::read(&mut &bytes[..]);
+});
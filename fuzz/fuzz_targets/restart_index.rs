@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qoi_rs::restart::RestartIndex;
+
+fuzz_target!(|bytes: &[u8]| {
+    let _ = RestartIndex::read(&mut &bytes[..]);
+});
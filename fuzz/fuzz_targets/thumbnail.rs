@@ -0,0 +1,16 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qoi_rs::thumbnail::{self, Filter};
+
+fuzz_target!(|input: (u32, u8, &[u8])| {
+    let (max_size, filter, bytes) = input;
+    let filter = match filter % 3 {
+        0 => Filter::Box,
+        1 => Filter::Bilinear,
+        _ => Filter::Lanczos3,
+    };
+
+    let _ = thumbnail::thumbnail::<3>(&mut &bytes[..], max_size, filter);
+    let _ = thumbnail::thumbnail::<4>(&mut &bytes[..], max_size, filter);
+});
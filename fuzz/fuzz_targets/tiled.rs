@@ -0,0 +1,16 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+use qoi_rs::tiled::TiledImage;
+
+// NB: The region is kept small, as its pixels are allocated before any tile is read
+fuzz_target!(|input: (u16, u16, u8, u8, &[u8])| {
+    let (x, y, width, height, bytes) = input;
+    if let Ok(mut image) = TiledImage::open(Cursor::new(bytes)) {
+        let (x, y, width, height) = (x.into(), y.into(), width.into(), height.into());
+        let _ = image.decode_region::<3>(x, y, width, height);
+        let _ = image.decode_region::<4>(x, y, width, height);
+    }
+});
@@ -0,0 +1,165 @@
+//! Conversion between straight and premultiplied alpha
+//!
+//! QOI always stores straight (unassociated) alpha. These helpers convert at the boundaries of
+//! [`encode_with_options`](crate::encode_with_options) and
+//! [`decode_with_options`](crate::decode_with_options) for callers that work in premultiplied
+//! alpha.
+//!
+//! # Rounding
+//! Both directions round to the nearest integer:
+//!
+//! - Premultiplying computes `c' = (c * a + 127) / 255`, which is exactly `round(c * a / 255)`
+//!   since `c * a / 255` can never land on a half.
+//! - Un-premultiplying computes `c = min((c' * 255 + a / 2) / a, 255)`, ie. `round(c' * 255 / a)`
+//!   with ties rounded up and clamped to `255`. A pixel with `a == 0` becomes `(0, 0, 0, 0)`.
+//!
+//! # Lossless round-trips
+//! - Premultiplied → straight → premultiplied is lossless for every valid premultiplied pixel,
+//!   ie. when no colour channel exceeds alpha. Encoding premultiplied input and decoding it back
+//!   as premultiplied therefore returns the exact input.
+//! - Straight → premultiplied → straight is only lossless when `a == 255`. Lower alpha values
+//!   quantise the colour channels to `a + 1` levels, and `a == 0` discards colour entirely.
+
+use crate::pixel::{Pixel, SupportedChannels};
+
+/// How the alpha channel of pixels given to or returned by the codec is interpreted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AlphaMode {
+    /// Colour channels are independent of alpha, as stored in QOI
+    #[default]
+    Straight,
+
+    /// Colour channels have already been multiplied by alpha
+    Premultiplied,
+}
+
+impl AlphaMode {
+    /// Converts a `pixel` in this mode into straight alpha
+    pub(crate) fn to_straight<const N: usize>(self, pixel: Pixel<N>) -> Pixel<N>
+    where
+        Pixel<N>: SupportedChannels,
+    {
+        match self {
+            AlphaMode::Straight => pixel,
+            AlphaMode::Premultiplied => unpremultiply(pixel),
+        }
+    }
+
+    /// Converts a straight alpha `pixel` into this mode
+    pub(crate) fn straight_into<const N: usize>(self, pixel: Pixel<N>) -> Pixel<N>
+    where
+        Pixel<N>: SupportedChannels,
+    {
+        match self {
+            AlphaMode::Straight => pixel,
+            AlphaMode::Premultiplied => premultiply(pixel),
+        }
+    }
+}
+
+/// Multiplies the colour channels of a straight alpha `pixel` by its alpha
+pub fn premultiply<const N: usize>(pixel: Pixel<N>) -> Pixel<N>
+where
+    Pixel<N>: SupportedChannels,
+{
+    let alpha = pixel.alpha() as u32;
+    if alpha == 255 {
+        return pixel;
+    }
+
+    let multiply = |channel: u8| ((channel as u32 * alpha + 127) / 255) as u8;
+
+    Pixel::from_inner_rgba([
+        multiply(pixel.red()),
+        multiply(pixel.green()),
+        multiply(pixel.blue()),
+        pixel.alpha(),
+    ])
+}
+
+/// Divides the colour channels of a premultiplied `pixel` by its alpha
+pub fn unpremultiply<const N: usize>(pixel: Pixel<N>) -> Pixel<N>
+where
+    Pixel<N>: SupportedChannels,
+{
+    let alpha = pixel.alpha() as u32;
+    match alpha {
+        0 => return Pixel::from_inner_rgba([0, 0, 0, 0]),
+        255 => return pixel,
+        _ => {}
+    }
+
+    let divide = |channel: u8| ((channel as u32 * 255 + alpha / 2) / alpha).min(255) as u8;
+
+    Pixel::from_inner_rgba([
+        divide(pixel.red()),
+        divide(pixel.green()),
+        divide(pixel.blue()),
+        pixel.alpha(),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn premultiply_rounds_to_nearest() {
+        assert_eq!(
+            premultiply(Pixel::rgba(255, 128, 1, 128)),
+            Pixel::rgba(128, 64, 1, 128)
+        );
+        assert_eq!(
+            premultiply(Pixel::rgba(200, 100, 50, 0)),
+            Pixel::rgba(0, 0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn unpremultiply_rounds_to_nearest() {
+        assert_eq!(
+            unpremultiply(Pixel::rgba(128, 64, 1, 128)),
+            Pixel::rgba(255, 128, 2, 128)
+        );
+        assert_eq!(
+            unpremultiply(Pixel::rgba(10, 20, 30, 0)),
+            Pixel::rgba(0, 0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn unpremultiply_clamps_invalid_input() {
+        assert_eq!(
+            unpremultiply(Pixel::rgba(200, 0, 0, 100)),
+            Pixel::rgba(255, 0, 0, 100)
+        );
+    }
+
+    #[test]
+    fn premultiplied_round_trip_is_lossless() {
+        for alpha in 0..=255u8 {
+            for channel in 0..=alpha {
+                let pixel = Pixel::rgba(channel, alpha - channel, channel / 2, alpha);
+                assert_eq!(premultiply(unpremultiply(pixel)), pixel);
+            }
+        }
+    }
+
+    #[test]
+    fn straight_round_trip_is_lossless_when_opaque() {
+        for channel in 0..=255u8 {
+            let pixel = Pixel::rgba(channel, 255 - channel, channel / 2, 255);
+            assert_eq!(unpremultiply(premultiply(pixel)), pixel);
+        }
+
+        let pixel = Pixel::rgba(101, 102, 103, 2);
+        assert_ne!(unpremultiply(premultiply(pixel)), pixel);
+    }
+
+    #[test]
+    fn rgb_is_unaffected() {
+        let pixel = Pixel::rgb(1, 2, 3);
+        assert_eq!(premultiply(pixel), pixel);
+        assert_eq!(unpremultiply(pixel), pixel);
+    }
+}
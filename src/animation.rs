@@ -0,0 +1,459 @@
+//! Animations made of a sequence of QOI images
+//!
+//! Like in APNG, each frame covers a region of a fixed size canvas, is shown for its own delay and
+//! specifies how its region is disposed of before the next frame. Unlike APNG, a frame always
+//! replaces the pixels of its region, without blending with the canvas. The layout of an
+//! animation is, with all integers in big-endian:
+//!
+//! | Field                          | Size in bytes  |
+//! |--------------------------------|----------------|
+//! | Magic bytes `b"qoia"`          | 4              |
+//! | `width`, `height`              | 4 + 4          |
+//! | `loop_count`                   | 4              |
+//! | Number of frames               | 4              |
+//! | Frames                         |                |
+//!
+//! Each frame starts with its `x`, `y` and `delay` in milliseconds as `u32`s, its [`Disposal`] as
+//! a `u8`, and the size of its image as a `u32`, followed by its image encoded with
+//! [`encode`](crate::encode).
+
+use std::time::Duration;
+
+use num::FromPrimitive;
+
+use crate::{
+    header::ColorSpace,
+    io::{Reader, Take, Writer},
+    pixel::Pixel,
+    Error, Result,
+};
+
+/// Magic bytes of an animation
+const ANIMATION_MAGIC: &[u8; 4] = b"qoia";
+
+/// Maximum number of frames allocated up front, before any of them are read
+const MAX_PREALLOCATED_FRAMES: usize = 1 << 10;
+
+/// What happens to the region of a frame before the next frame is drawn
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+pub enum Disposal {
+    /// The region is left as is
+    #[default]
+    None = 0,
+
+    /// The region is cleared to fully transparent black
+    Background = 1,
+
+    /// The region is restored to what it was before the frame was drawn
+    Previous = 2,
+}
+
+/// A single frame of an [`Animation`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    /// Horizontal offset of the frame on the canvas
+    pub x: u32,
+
+    /// Vertical offset of the frame on the canvas
+    pub y: u32,
+
+    pub width: u32,
+    pub height: u32,
+
+    /// Time the frame is shown for, which is stored in whole milliseconds
+    pub delay: Duration,
+
+    pub disposal: Disposal,
+
+    pub pixels: Vec<Pixel<4>>,
+}
+
+/// An animation of [`Frame`]s drawn onto a canvas of `width` and `height`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Animation {
+    pub width: u32,
+    pub height: u32,
+
+    /// Number of times the animation is played, where `0` loops forever
+    pub loop_count: u32,
+
+    pub frames: Vec<Frame>,
+}
+
+impl Animation {
+    /// Draws every frame onto the canvas in order, passing the whole canvas as shown during each
+    /// frame to `shown` before the next frame is drawn. The canvas starts out fully transparent
+    /// black.
+    ///
+    /// Only a single canvas is held at any time, along with the region of the current frame if it
+    /// is restored to what it was before.
+    ///
+    /// # Errors
+    /// This function returns `Err` if a frame is not entirely within the canvas
+    /// ([`Error::InvalidRegion`]), the size of its `pixels` differs from its dimensions
+    /// ([`Error::UnmatchedDataSize`]), the canvas cannot be allocated ([`Error::IoError`]) or
+    /// `shown` fails. Every frame is checked before the canvas is allocated.
+    pub fn render(&self, mut shown: impl FnMut(&[Pixel<4>]) -> Result<()>) -> Result<()> {
+        for frame in &self.frames {
+            self.check_frame(frame)?;
+        }
+        if self.frames.is_empty() {
+            return Ok(());
+        }
+
+        // NB: The canvas is as large as the animation claims, so it is allocated fallibly
+        let canvas_width = self.width as usize;
+        let mut canvas = reserve(self.width, self.height)?;
+        canvas.resize(canvas_width * self.height as usize, Pixel::rgba(0, 0, 0, 0));
+
+        for frame in &self.frames {
+            let rows = (frame.y as usize..(frame.y + frame.height) as usize)
+                .map(|y| y * canvas_width + frame.x as usize)
+                .map(|start| start..start + frame.width as usize);
+
+            let previous: Option<Vec<_>> = match frame.disposal {
+                Disposal::Previous => Some(
+                    rows.clone()
+                        .flat_map(|row| canvas[row].iter().copied())
+                        .collect(),
+                ),
+                _ => None,
+            };
+
+            for (row, pixels) in rows
+                .clone()
+                .zip(frame.pixels.chunks(frame.width.max(1) as usize))
+            {
+                canvas[row].copy_from_slice(pixels);
+            }
+            shown(&canvas)?;
+
+            match frame.disposal {
+                Disposal::None => {}
+                Disposal::Background => {
+                    for row in rows {
+                        canvas[row].fill(Pixel::rgba(0, 0, 0, 0));
+                    }
+                }
+                Disposal::Previous => {
+                    let previous = previous.unwrap();
+                    for (row, pixels) in rows.zip(previous.chunks(frame.width.max(1) as usize)) {
+                        canvas[row].copy_from_slice(pixels);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Ensures `frame` is within the canvas and has as many pixels as its dimensions
+    fn check_frame(&self, frame: &Frame) -> Result<()> {
+        let within = |start: u32, length: u32, size: u32| {
+            start.checked_add(length).is_some_and(|end| end <= size)
+        };
+        if !within(frame.x, frame.width, self.width) || !within(frame.y, frame.height, self.height)
+        {
+            return Err(Error::InvalidRegion {
+                x: frame.x,
+                y: frame.y,
+                width: frame.width,
+                height: frame.height,
+            });
+        }
+
+        let frame_size = frame.width as usize * frame.height as usize;
+        if frame.pixels.len() != frame_size {
+            return Err(Error::UnmatchedDataSize {
+                data_size: frame.pixels.len(),
+                header_size: frame_size,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Allocates room for exactly `width` by `height` items, as claimed by an untrusted header
+fn reserve<T>(width: u32, height: u32) -> Result<Vec<T>> {
+    let mut items = Vec::new();
+    (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|size| items.try_reserve_exact(size).ok())
+        .ok_or_else(|| Error::IoError(std::io::ErrorKind::OutOfMemory.into()))?;
+
+    Ok(items)
+}
+
+/// Encodes the `animation`, with each frame encoded with [`encode`](crate::encode) in
+/// `color_space`, then writing it into the provided `writer`.
+///
+/// The function returns the number of bytes written to the `writer`.
+///
+/// # Errors
+/// This function returns `Err` in one of the following cases:
+///
+/// 1. Either [`Writer::write_byte`] or [`Writer::write_from_slice`] fails.
+/// 2. A frame is not entirely within the canvas ([`Error::InvalidRegion`])
+/// 3. The size of the `pixels` of a frame differs from its dimensions
+///    ([`Error::UnmatchedDataSize`])
+pub fn encode(
+    writer: &mut impl Writer,
+    animation: &Animation,
+    color_space: ColorSpace,
+) -> Result<usize> {
+    let mut written = 0;
+
+    // Write header information
+    written += writer.write_from_slice(ANIMATION_MAGIC)?;
+    written += writer.write_from_slice(&animation.width.to_be_bytes())?;
+    written += writer.write_from_slice(&animation.height.to_be_bytes())?;
+    written += writer.write_from_slice(&animation.loop_count.to_be_bytes())?;
+    written += writer.write_from_slice(&(animation.frames.len() as u32).to_be_bytes())?;
+
+    for frame in &animation.frames {
+        animation.check_frame(frame)?;
+
+        let mut image = vec![];
+        crate::encode(
+            &mut image,
+            &frame.pixels,
+            frame.width,
+            frame.height,
+            color_space,
+        )?;
+
+        // NB: Delays beyond `u32::MAX` milliseconds are saturated
+        let delay = u32::try_from(frame.delay.as_millis()).unwrap_or(u32::MAX);
+
+        written += writer.write_from_slice(&frame.x.to_be_bytes())?;
+        written += writer.write_from_slice(&frame.y.to_be_bytes())?;
+        written += writer.write_from_slice(&delay.to_be_bytes())?;
+        written += writer.write_byte(frame.disposal as u8)?;
+        written += writer.write_from_slice(&(image.len() as u32).to_be_bytes())?;
+        written += writer.write_from_slice(&image)?;
+    }
+
+    Ok(written)
+}
+
+/// Decodes an animation from the provided `reader`, returning it along with the color space of
+/// its first frame, which is `None` if it has no frames.
+///
+/// # Errors
+/// This function returns `Err` in one of the following cases:
+///
+/// 1. Reading from the `reader` fails, including when the data ends early or the image of a frame
+///    runs past its size.
+/// 2. The magic bytes are invalid ([`Error::InvalidMagic`]), or a frame has an invalid
+///    [`Disposal`] ([`Error::InvalidDisposal`])
+/// 3. Decoding the image of a frame fails, see [`decode`](crate::decode)
+/// 4. A frame is not entirely within the canvas ([`Error::InvalidRegion`])
+pub fn decode(reader: &mut impl Reader) -> Result<(Animation, Option<ColorSpace>)> {
+    let mut magic = [0; 4];
+    reader.read_to_slice(&mut magic)?;
+    if &magic != ANIMATION_MAGIC {
+        return Err(Error::InvalidMagic(magic));
+    }
+
+    let width = read_u32(reader)?;
+    let height = read_u32(reader)?;
+    let loop_count = read_u32(reader)?;
+    let frame_count = read_u32(reader)? as usize;
+
+    let mut animation = Animation {
+        width,
+        height,
+        loop_count,
+        // NB: The frame count is not trusted with the allocation, as the data may end early
+        frames: Vec::with_capacity(frame_count.min(MAX_PREALLOCATED_FRAMES)),
+    };
+    let mut color_space = None;
+
+    for _ in 0..frame_count {
+        let x = read_u32(reader)?;
+        let y = read_u32(reader)?;
+        let delay = Duration::from_millis(read_u32(reader)? as u64);
+
+        let disposal = reader.read_byte()?;
+        let disposal = Disposal::from_u8(disposal).ok_or(Error::InvalidDisposal(disposal))?;
+
+        // NB: The image is decoded within its size, skipping any bytes left after it
+        let size = read_u32(reader)?;
+        let mut image = Take::new(reader, size as u64);
+        let (header, pixels) = crate::decode::<4>(&mut image)?;
+        image.skip_remaining()?;
+        color_space.get_or_insert(header.color_space());
+
+        let frame = Frame {
+            x,
+            y,
+            width: header.width(),
+            height: header.height(),
+            delay,
+            disposal,
+            pixels,
+        };
+        animation.check_frame(&frame)?;
+        animation.frames.push(frame);
+    }
+
+    Ok((animation, color_space))
+}
+
+fn read_u32(reader: &mut impl Reader) -> Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_to_slice(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(x: u32, y: u32, width: u32, height: u32, value: u8, disposal: Disposal) -> Frame {
+        Frame {
+            x,
+            y,
+            width,
+            height,
+            delay: Duration::from_millis(value as u64 * 10),
+            disposal,
+            pixels: vec![Pixel::rgba(value, value, value, 255); (width * height) as usize],
+        }
+    }
+
+    fn animation() -> Animation {
+        Animation {
+            width: 4,
+            height: 3,
+            loop_count: 2,
+            frames: vec![
+                frame(0, 0, 4, 3, 1, Disposal::None),
+                frame(1, 1, 2, 1, 2, Disposal::Previous),
+                frame(2, 0, 2, 2, 3, Disposal::Background),
+                frame(0, 2, 1, 1, 4, Disposal::None),
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let animation = animation();
+
+        let mut buf = vec![];
+        let written = encode(&mut buf, &animation, ColorSpace::AllLinear).unwrap();
+        assert_eq!(written, buf.len());
+
+        let (decoded, color_space) = decode(&mut buf.as_slice()).unwrap();
+        assert_eq!(decoded, animation);
+        assert_eq!(color_space, Some(ColorSpace::AllLinear));
+    }
+
+    #[test]
+    fn renders_with_disposal() {
+        let pixel = |value| Pixel::rgba(value, value, value, 255);
+        let clear = Pixel::rgba(0, 0, 0, 0);
+
+        let mut rendered = vec![];
+        animation()
+            .render(|canvas| {
+                rendered.push(canvas.to_vec());
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(rendered[0], vec![pixel(1); 12]);
+
+        let mut expected = vec![pixel(1); 12];
+        expected[5..7].fill(pixel(2));
+        assert_eq!(rendered[1], expected);
+
+        // NB: The second frame is restored to the first before the third is drawn
+        let mut expected = vec![pixel(1); 12];
+        for row in 0..2 {
+            expected[row * 4 + 2..row * 4 + 4].fill(pixel(3));
+        }
+        assert_eq!(rendered[2], expected);
+
+        // NB: The third frame is cleared before the fourth is drawn
+        for row in 0..2 {
+            expected[row * 4 + 2..row * 4 + 4].fill(clear);
+        }
+        expected[8] = pixel(4);
+        assert_eq!(rendered[3], expected);
+    }
+
+    #[test]
+    fn rejects_frame_outside_canvas() {
+        let mut animation = animation();
+        animation.frames.push(frame(3, 0, 2, 1, 5, Disposal::None));
+
+        assert!(matches!(
+            encode(&mut vec![], &animation, ColorSpace::Srgb),
+            Err(Error::InvalidRegion { x: 3, width: 2, .. })
+        ));
+        // NB: No frame is shown, as every frame is checked before any is drawn
+        let mut shown = 0;
+        assert!(matches!(
+            animation.render(|_| {
+                shown += 1;
+                Ok(())
+            }),
+            Err(Error::InvalidRegion { x: 3, width: 2, .. })
+        ));
+        assert_eq!(shown, 0);
+    }
+
+    #[test]
+    fn rejects_huge_canvas_before_allocating() {
+        let animation = Animation {
+            width: u32::MAX,
+            height: u32::MAX,
+            loop_count: 0,
+            frames: vec![frame(0, 0, 1, 1, 1, Disposal::None)],
+        };
+
+        assert!(matches!(
+            animation.render(|_| Ok(())),
+            Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::OutOfMemory
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_disposal() {
+        let mut buf = vec![];
+        encode(&mut buf, &animation(), ColorSpace::Srgb).unwrap();
+
+        // NB: Disposal of the first frame follows the header and its offsets and delay
+        buf[20 + 12] = 3;
+
+        assert!(matches!(
+            decode(&mut buf.as_slice()),
+            Err(Error::InvalidDisposal(3))
+        ));
+    }
+
+    #[test]
+    fn decodes_frames_within_their_size() {
+        let mut buf = vec![];
+        encode(&mut buf, &animation(), ColorSpace::Srgb).unwrap();
+
+        // NB: Size of the first frame follows its disposal
+        let size = u32::from_be_bytes(buf[33..37].try_into().unwrap());
+        let end = 37 + size as usize;
+
+        // Bytes left after an image are skipped
+        let mut padded = buf.clone();
+        padded[33..37].copy_from_slice(&(size + 3).to_be_bytes());
+        padded.splice(end..end, [0xaa; 3]);
+        assert_eq!(decode(&mut padded.as_slice()).unwrap().0, animation());
+
+        // An image running past its size is rejected
+        buf[33..37].copy_from_slice(&(size - 1).to_be_bytes());
+        assert!(matches!(
+            decode(&mut buf.as_slice()),
+            Err(Error::IoError(error)) if error.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+    }
+}
@@ -0,0 +1,125 @@
+//! Conversion between the sRGB and linear [`ColorSpace`]s
+//!
+//! The color space stored in a QOI header is purely informative and does not change how pixels
+//! are encoded. These helpers let [`encode_with_options`](crate::encode_with_options) and
+//! [`decode_with_options`](crate::decode_with_options) act on it, so that pixels end up in the
+//! space the caller asked for.
+//!
+//! Conversions use the standard sRGB transfer function through 256-entry lookup tables, rounding
+//! to the nearest 8-bit value. Only the colour channels are converted, alpha is always linear.
+//!
+//! As 8-bit sRGB and 8-bit linear values quantise the range differently, converting back and
+//! forth is lossy in both directions: dark linear values and bright sRGB values collapse together.
+
+use std::sync::OnceLock;
+
+use crate::{
+    header::ColorSpace,
+    pixel::{Pixel, SupportedChannels},
+};
+
+/// Converts an 8-bit sRGB encoded `channel` into its 8-bit linear value
+pub fn srgb_to_linear(channel: u8) -> u8 {
+    static TABLE: OnceLock<[u8; 256]> = OnceLock::new();
+
+    TABLE.get_or_init(|| {
+        build_table(|value| {
+            if value <= 0.04045 {
+                value / 12.92
+            } else {
+                ((value + 0.055) / 1.055).powf(2.4)
+            }
+        })
+    })[channel as usize]
+}
+
+/// Converts an 8-bit linear `channel` into its 8-bit sRGB encoded value
+pub fn linear_to_srgb(channel: u8) -> u8 {
+    static TABLE: OnceLock<[u8; 256]> = OnceLock::new();
+
+    TABLE.get_or_init(|| {
+        build_table(|value| {
+            if value <= 0.0031308 {
+                value * 12.92
+            } else {
+                1.055 * value.powf(1.0 / 2.4) - 0.055
+            }
+        })
+    })[channel as usize]
+}
+
+/// Converts the colour channels of `pixel` from color space `from` into `to`
+pub fn convert<const N: usize>(pixel: Pixel<N>, from: ColorSpace, to: ColorSpace) -> Pixel<N>
+where
+    Pixel<N>: SupportedChannels,
+{
+    let transfer = match (from, to) {
+        (ColorSpace::Srgb, ColorSpace::AllLinear) => srgb_to_linear,
+        (ColorSpace::AllLinear, ColorSpace::Srgb) => linear_to_srgb,
+        _ => return pixel,
+    };
+
+    Pixel::from_inner_rgba([
+        transfer(pixel.red()),
+        transfer(pixel.green()),
+        transfer(pixel.blue()),
+        pixel.alpha(),
+    ])
+}
+
+/// Builds a lookup table by applying `transfer` to every normalised 8-bit value
+fn build_table(transfer: impl Fn(f64) -> f64) -> [u8; 256] {
+    let mut table = [0; 256];
+
+    for (value, entry) in table.iter_mut().enumerate() {
+        *entry = (transfer(value as f64 / 255.0) * 255.0)
+            .round()
+            .clamp(0.0, 255.0) as u8;
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoints_are_preserved() {
+        assert_eq!(srgb_to_linear(0), 0);
+        assert_eq!(srgb_to_linear(255), 255);
+        assert_eq!(linear_to_srgb(0), 0);
+        assert_eq!(linear_to_srgb(255), 255);
+    }
+
+    #[test]
+    fn converts_mid_grey() {
+        assert_eq!(srgb_to_linear(128), 55);
+        assert_eq!(linear_to_srgb(55), 128);
+        assert_eq!(srgb_to_linear(10), 1);
+        assert_eq!(linear_to_srgb(1), 13);
+    }
+
+    #[test]
+    fn tables_are_monotonic() {
+        for channel in 1..=255u8 {
+            assert!(srgb_to_linear(channel - 1) <= srgb_to_linear(channel));
+            assert!(linear_to_srgb(channel - 1) <= linear_to_srgb(channel));
+        }
+    }
+
+    #[test]
+    fn convert_leaves_alpha_and_same_space_untouched() {
+        let pixel = Pixel::rgba(128, 255, 0, 128);
+
+        assert_eq!(
+            convert(pixel, ColorSpace::Srgb, ColorSpace::AllLinear),
+            Pixel::rgba(55, 255, 0, 128)
+        );
+        assert_eq!(convert(pixel, ColorSpace::Srgb, ColorSpace::Srgb), pixel);
+        assert_eq!(
+            convert(pixel, ColorSpace::AllLinear, ColorSpace::AllLinear),
+            pixel
+        );
+    }
+}
@@ -0,0 +1,249 @@
+//! QOI images wrapped in a general-purpose compressor
+//!
+//! The `QOI_OP`s of an image still hold redundancy, such as the same sequence of ops repeating on
+//! every row of a pattern, which a general-purpose compressor removes at some cost in speed. Each
+//! compressor is enabled by the feature of the same name. The layout of a compressed image is,
+//! with all integers in big-endian:
+//!
+//! | Field                          | Size in bytes  |
+//! |--------------------------------|----------------|
+//! | Magic bytes `b"qoiz"`          | 4              |
+//! | Compressor                     | 1              |
+//! | Size of the compressed image   | 8              |
+//! | Compressed image               |                |
+//!
+//! The compressed image is a whole image encoded with [`encode`](crate::encode), compressed into
+//! an LZ4 frame (`1`) or a Zstandard frame (`2`). [`decode`] reads both compressed and plain QOI
+//! images.
+
+use crate::{
+    header::{ColorSpace, Header},
+    io::{read_untrusted, Reader, Writer},
+    pixel::{Pixel, SupportedChannels},
+    Error, Result,
+};
+
+/// Magic bytes of a compressed image
+const COMPRESSED_MAGIC: &[u8; 4] = b"qoiz";
+
+#[cfg(feature = "lz4")]
+const LZ4: u8 = 1;
+#[cfg(feature = "zstd")]
+const ZSTD: u8 = 2;
+
+/// General-purpose compressor applied to an image
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// LZ4, which is fast to both compress and decompress, but only removes repeated sequences
+    #[cfg(feature = "lz4")]
+    Lz4,
+
+    /// Zstandard at the given level, from `1` (fastest) to `22` (smallest), where `0` picks the
+    /// default level of the library
+    #[cfg(feature = "zstd")]
+    Zstd(i32),
+}
+
+/// Encodes the provided `pixels` with [`encode`](crate::encode), then compresses the whole image
+/// with `compression` before writing it into the provided `writer`.
+///
+/// The function returns the number of bytes written to the `writer`.
+///
+/// # Errors
+/// This function returns `Err` in one of the following cases:
+///
+/// 1. Either [`Writer::write_byte`] or [`Writer::write_from_slice`] fails, or compressing fails
+///    ([`Error::IoError`])
+/// 2. The provided `width` and `height` differs from the length of `pixels`
+///    ([`Error::UnmatchedDataSize`])
+pub fn encode<const N: usize>(
+    writer: &mut impl Writer,
+    pixels: &[Pixel<N>],
+    width: u32,
+    height: u32,
+    color_space: ColorSpace,
+    compression: Compression,
+) -> Result<usize>
+where
+    Pixel<N>: SupportedChannels,
+{
+    let mut image = vec![];
+    crate::encode(&mut image, pixels, width, height, color_space)?;
+
+    let (compressor, compressed) = match compression {
+        #[cfg(feature = "lz4")]
+        Compression::Lz4 => {
+            use std::io::Write;
+
+            let mut encoder = lz4_flex::frame::FrameEncoder::new(vec![]);
+            encoder.write_all(&image).map_err(Error::IoError)?;
+            let compressed = encoder.finish().map_err(|err| Error::IoError(err.into()))?;
+
+            (LZ4, compressed)
+        }
+        #[cfg(feature = "zstd")]
+        Compression::Zstd(level) => {
+            let compressed = zstd::encode_all(image.as_slice(), level).map_err(Error::IoError)?;
+
+            (ZSTD, compressed)
+        }
+    };
+
+    let mut written = 0;
+
+    // Write header information
+    written += writer.write_from_slice(COMPRESSED_MAGIC)?;
+    written += writer.write_byte(compressor)?;
+    written += writer.write_from_slice(&(compressed.len() as u64).to_be_bytes())?;
+
+    written += writer.write_from_slice(&compressed)?;
+
+    Ok(written)
+}
+
+/// Decodes either a compressed or a plain QOI image from the provided `reader`, returning its
+/// [`Header`] and pixels as [`decode`](crate::decode) does.
+///
+/// # Errors
+/// This function returns `Err` in one of the following cases:
+///
+/// 1. Reading from the `reader` fails, including when the data ends early, or decompressing fails
+///    ([`Error::IoError`])
+/// 2. The image is compressed with an unknown compressor, or one not enabled
+///    ([`Error::UnsupportedCompression`])
+/// 3. Decoding the image fails, see [`decode`](crate::decode)
+pub fn decode<const N: usize>(reader: &mut impl Reader) -> Result<(Header, Vec<Pixel<N>>)>
+where
+    Pixel<N>: SupportedChannels,
+{
+    let mut magic = [0; 4];
+    reader.read_to_slice(&mut magic)?;
+    if &magic != COMPRESSED_MAGIC {
+        // NB: Anything else is left to the plain decoder, which reads the magic bytes again
+        return crate::decode(&mut Prefixed {
+            prefix: &magic,
+            inner: reader,
+        });
+    }
+
+    let compressor = reader.read_byte()?;
+
+    let mut bytes = [0; 8];
+    reader.read_to_slice(&mut bytes)?;
+    let size = usize::try_from(u64::from_be_bytes(bytes)).unwrap_or(usize::MAX);
+    let compressed = read_untrusted(reader, size)?;
+
+    match compressor {
+        #[cfg(feature = "lz4")]
+        LZ4 => crate::decode(&mut lz4_flex::frame::FrameDecoder::new(
+            compressed.as_slice(),
+        )),
+        #[cfg(feature = "zstd")]
+        ZSTD => crate::decode(
+            &mut zstd::Decoder::with_buffer(compressed.as_slice()).map_err(Error::IoError)?,
+        ),
+        _ => Err(Error::UnsupportedCompression(compressor)),
+    }
+}
+
+/// A [`Reader`] yielding the bytes of `prefix` before those of `inner`
+struct Prefixed<'a, R> {
+    prefix: &'a [u8],
+    inner: &'a mut R,
+}
+
+impl<R: Reader> Reader for Prefixed<'_, R> {
+    fn read_to_slice(&mut self, bytes: &mut [u8]) -> Result<usize> {
+        let (head, tail) = self.prefix.split_at(self.prefix.len().min(bytes.len()));
+        bytes[..head.len()].copy_from_slice(head);
+        self.prefix = tail;
+
+        if head.len() < bytes.len() {
+            self.inner.read_to_slice(&mut bytes[head.len()..])?;
+        }
+
+        Ok(bytes.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A repeating pattern, which QOI alone encodes the same way on every row
+    fn pattern(width: u32, height: u32) -> Vec<Pixel<4>> {
+        (0..width * height)
+            .map(|i| {
+                let x = (i % width) as u8;
+                Pixel::rgba(x.wrapping_mul(37), x.wrapping_mul(91), x ^ 0x5a, 255)
+            })
+            .collect()
+    }
+
+    fn compressions() -> Vec<Compression> {
+        vec![
+            #[cfg(feature = "lz4")]
+            Compression::Lz4,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(0),
+        ]
+    }
+
+    #[test]
+    fn round_trips() {
+        let (width, height) = (64, 32);
+        let pixels = pattern(width, height);
+
+        let mut plain = vec![];
+        crate::encode(&mut plain, &pixels, width, height, ColorSpace::Srgb).unwrap();
+
+        for compression in compressions() {
+            let mut buf = vec![];
+            let written = encode(
+                &mut buf,
+                &pixels,
+                width,
+                height,
+                ColorSpace::Srgb,
+                compression,
+            )
+            .unwrap();
+            assert_eq!(written, buf.len());
+            assert!(buf.len() < plain.len(), "{compression:?}");
+
+            let (header, decoded) = decode::<4>(&mut buf.as_slice()).unwrap();
+            assert_eq!(header.width(), width);
+            assert_eq!(decoded, pixels);
+        }
+    }
+
+    #[test]
+    fn decodes_plain_images() {
+        let pixels = pattern(8, 8);
+
+        let mut buf = vec![];
+        crate::encode(&mut buf, &pixels, 8, 8, ColorSpace::Srgb).unwrap();
+
+        assert_eq!(decode::<4>(&mut buf.as_slice()).unwrap().1, pixels);
+    }
+
+    #[test]
+    fn rejects_unknown_compressor() {
+        let mut buf = vec![];
+        encode(
+            &mut buf,
+            &pattern(4, 4),
+            4,
+            4,
+            ColorSpace::Srgb,
+            compressions()[0],
+        )
+        .unwrap();
+        buf[4] = 0xff;
+
+        assert!(matches!(
+            decode::<4>(&mut buf.as_slice()),
+            Err(Error::UnsupportedCompression(0xff))
+        ));
+    }
+}
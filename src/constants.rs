@@ -1,4 +1,5 @@
 pub(crate) const QOI_MAGIC: &[u8; 4] = b"qoif";
+pub(crate) const QOI_EXTENDED_MAGIC: &[u8; 4] = b"qoie";
 pub(crate) const QOI_END_MARKER: &[u8; 8] = b"\x00\x00\x00\x00\x00\x00\x00\x01";
 
 pub(crate) const QOI_OP_RGB: u8 = 0b1111_1110;
@@ -8,3 +9,19 @@ pub(crate) const QOI_OP_INDEX: u8 = 0b0000_0000;
 pub(crate) const QOI_OP_DIFF: u8 = 0b0100_0000;
 pub(crate) const QOI_OP_LUMA: u8 = 0b1000_0000;
 pub(crate) const QOI_OP_RUN: u8 = 0b1100_0000;
+
+pub(crate) const QOI_OP_MASK: u8 = 0b1100_0000;
+
+/// Maximum number of pixels a single `QOI_OP_RUN` stands for
+pub(crate) const QOI_MAX_RUN: usize = 62;
+
+/// Tags only found in the extended profile, see [`crate::profile`]
+pub(crate) const QOI_OP_ALPHA: u8 = 0b1111_1011;
+pub(crate) const QOI_OP_RUN_LONG: u8 = 0b1111_1100;
+pub(crate) const QOI_OP_INDEX_LONG: u8 = 0b1111_1101;
+
+/// Maximum number of pixels a single `QOI_OP_RUN` stands for in the extended profile
+pub(crate) const QOI_EXTENDED_MAX_RUN: usize = 59;
+
+/// Maximum number of pixels a single `QOI_OP_RUN_LONG` stands for
+pub(crate) const QOI_MAX_LONG_RUN: usize = 1 << 16;
@@ -0,0 +1,101 @@
+//! CRC-32 as used by PNG and zlib (ISO-HDLC, reflected polynomial `0xedb88320`)
+
+use crate::{
+    io::{Reader, Writer},
+    Result,
+};
+
+/// Lookup table of the CRC of every byte
+static TABLE: [u32; 256] = build_table();
+
+const fn build_table() -> [u32; 256] {
+    let mut table = [0; 256];
+
+    let mut byte = 0;
+    while byte < 256 {
+        let mut crc = byte as u32;
+
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 1 {
+                1 => 0xedb8_8320 ^ (crc >> 1),
+                _ => crc >> 1,
+            };
+            bit += 1;
+        }
+
+        table[byte] = crc;
+        byte += 1;
+    }
+
+    table
+}
+
+/// A running CRC-32 over data fed through [`Crc32::update`]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Crc32(u32);
+
+impl Crc32 {
+    pub(crate) fn new() -> Self {
+        Self(u32::MAX)
+    }
+
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = TABLE[((self.0 ^ byte as u32) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub(crate) fn finish(self) -> u32 {
+        !self.0
+    }
+}
+
+/// A [`Reader`] or [`Writer`] keeping a running CRC-32 of every byte passing through it
+pub(crate) struct Checksummed<'a, T> {
+    inner: &'a mut T,
+    crc: Crc32,
+}
+
+impl<'a, T> Checksummed<'a, T> {
+    pub(crate) fn new(inner: &'a mut T) -> Self {
+        Self {
+            inner,
+            crc: Crc32::new(),
+        }
+    }
+
+    pub(crate) fn finish(self) -> u32 {
+        self.crc.finish()
+    }
+}
+
+impl<T: Reader> Reader for Checksummed<'_, T> {
+    fn read_to_slice(&mut self, bytes: &mut [u8]) -> Result<usize> {
+        let read = self.inner.read_to_slice(bytes)?;
+        self.crc.update(&bytes[..read]);
+        Ok(read)
+    }
+}
+
+impl<T: Writer> Writer for Checksummed<'_, T> {
+    fn write_from_slice(&mut self, bytes: &[u8]) -> Result<usize> {
+        let written = self.inner.write_from_slice(bytes)?;
+        self.crc.update(&bytes[..written]);
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_value() {
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+
+        assert_eq!(crc.finish(), 0xcbf4_3926);
+    }
+}
@@ -0,0 +1,641 @@
+use crate::{
+    alpha::AlphaMode,
+    color_space,
+    constants::{
+        QOI_END_MARKER, QOI_OP_ALPHA, QOI_OP_DIFF, QOI_OP_INDEX, QOI_OP_INDEX_LONG, QOI_OP_LUMA,
+        QOI_OP_MASK, QOI_OP_RGB, QOI_OP_RGBA, QOI_OP_RUN, QOI_OP_RUN_LONG,
+    },
+    crc::Checksummed,
+    header::{ColorSpace, Header},
+    io::Reader,
+    metadata::{self, Chunk},
+    orientation::{Crop, Placement, Rotation},
+    pixel::{Pixel, SupportedChannels},
+    profile::{self, Profile},
+    traversal::Traversal,
+    Error, Result,
+};
+
+/// Maximum number of pixels allocated up front, before any of them are decoded
+pub(crate) const MAX_PREALLOCATED_PIXELS: usize = 1 << 20;
+
+/// Options controlling how [`decode_with_options`] produces its output
+///
+/// Cropping and flipping horizontally are applied to the rows of row-major images as they are
+/// decoded. Flipping vertically or rotating, or cropping or flipping an image stored in another
+/// [`Traversal`], places the pixels once the whole image is decoded, so its pixels are held along
+/// with those returned.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DecodeOptions {
+    /// Alpha representation of the returned pixels, converted from the straight alpha stored in
+    /// the file. See [`crate::alpha`] for the rounding rules.
+    pub alpha: AlphaMode,
+
+    /// Color space of the returned pixels. If it differs from the `color_space` in the header,
+    /// pixels are converted into the requested space. `None` keeps the space of the file. See
+    /// [`crate::color_space`] for the conversion used.
+    pub color_space: Option<ColorSpace>,
+
+    /// Whether to read the [`crate::metadata`] trailer following the end marker, if any, and
+    /// validate the image against its [`Chunk::Checksum`]. Images without a checksum are accepted.
+    ///
+    /// NB: The trailer is consumed from the `reader`, so this is only suited to standalone images.
+    pub verify_checksum: bool,
+
+    /// Rectangle of the image to return, or `None` for the whole image. See
+    /// [`crate::orientation`] for how it combines with flips and rotations.
+    pub crop: Option<Crop>,
+
+    /// Whether to mirror the returned pixels left to right
+    pub flip_horizontal: bool,
+
+    /// Whether to mirror the returned pixels top to bottom, as for textures with their origin at
+    /// the bottom left
+    pub flip_vertical: bool,
+
+    /// Clockwise rotation of the returned pixels, which swaps the width and height of the returned
+    /// [`Header`] for odd multiples of 90°
+    pub rotation: Rotation,
+}
+
+/// Decodes a QOI image from the provided `reader`, returning its [`Header`] and pixels.
+///
+/// The whole file is consumed, including the header, `QOI_OP`s and the end marker.
+///
+/// Images of either [`Profile`] are decoded, telling them apart by their magic bytes.
+///
+/// The pixels are returned with `N` channels regardless of the number of `channels` specified in
+/// the header. Decoding an RGBA image into [`Pixel<3>`] drops the alpha channel, while decoding an
+/// RGB image into [`Pixel<4>`] yields fully opaque pixels.
+///
+/// # Errors
+/// This function returns `Err` in one of the following cases:
+///
+/// 1. Either [`Reader::read_byte`] or [`Reader::read_to_slice`] fails, including when the data
+///    ends early.
+/// 2. The header is invalid (see [`Error::InvalidMagic`], [`Error::InvalidChannelNumber`],
+///    [`Error::InvalidColorSpace`], [`Error::InvalidTransform`] and [`Error::InvalidTraversal`])
+/// 3. The end marker does not follow the last pixel ([`Error::InvalidEndMarker`])
+pub fn decode<const N: usize>(reader: &mut impl Reader) -> Result<(Header, Vec<Pixel<N>>)>
+where
+    Pixel<N>: SupportedChannels,
+{
+    decode_with_options(reader, &DecodeOptions::default())
+}
+
+/// Same as [`decode`], but with `options` controlling the returned pixels. The returned [`Header`]
+/// holds the dimensions of the returned pixels, after any crop and rotation.
+///
+/// # Errors
+/// See [`decode`]. A crop not entirely within the image fails with [`Error::InvalidRegion`]. When
+/// verifying the checksum, reading the trailer may also fail as in [`metadata::read`], and a
+/// checksum not matching the image fails with [`Error::InvalidChecksum`].
+pub fn decode_with_options<const N: usize>(
+    reader: &mut impl Reader,
+    options: &DecodeOptions,
+) -> Result<(Header, Vec<Pixel<N>>)>
+where
+    Pixel<N>: SupportedChannels,
+{
+    if !options.verify_checksum {
+        return decode_image(reader, options);
+    }
+
+    let mut checksummed = Checksummed::new(reader);
+    let decoded = decode_image(&mut checksummed, options)?;
+    let actual = checksummed.finish();
+
+    for chunk in metadata::read(reader)? {
+        if let Chunk::Checksum(expected) = chunk {
+            if expected != actual {
+                return Err(Error::InvalidChecksum { expected, actual });
+            }
+        }
+    }
+
+    Ok(decoded)
+}
+
+/// Decodes a single image as described by [`decode_with_options`], leaving any trailer unread
+fn decode_image<const N: usize>(
+    reader: &mut impl Reader,
+    options: &DecodeOptions,
+) -> Result<(Header, Vec<Pixel<N>>)>
+where
+    Pixel<N>: SupportedChannels,
+{
+    // Read header information, which also tells the profile of the image
+    let (header, profile, extensions) = profile::read_header(reader)?;
+
+    let image_size = (header.width() as usize).saturating_mul(header.height() as usize);
+    let placement = Placement::new(
+        header.width(),
+        header.height(),
+        options.crop,
+        options.flip_horizontal,
+        options.flip_vertical,
+        options.rotation,
+    )?;
+    let placed = !placement.is_identity(header.width(), header.height());
+
+    let mut state = DecoderState::with_profile(profile);
+
+    let output_color_space = options.color_space.unwrap_or(header.color_space());
+    let emit = |pixel: Pixel<4>| {
+        let pixel = color_space::convert(
+            extensions
+                .transform
+                .inverse(Pixel::<N>::from_inner_rgba(pixel.as_inner_rgba())),
+            header.color_space(),
+            output_color_space,
+        );
+
+        options.alpha.straight_into(pixel)
+    };
+
+    // NB: Pixels of row-major images are placed as they are decoded unless flipped vertically or
+    // NB: rotated, as a crop only drops pixels and mirrored rows are reversed after. Otherwise,
+    // NB: every pixel is decoded in the order it is stored and only placed once all are.
+    let streamed = placed && extensions.traversal == Traversal::RowMajor && placement.keeps_rows();
+
+    let (width, height) = placement.size();
+    let stride = header.width() as usize;
+
+    // NB: The header alone cannot be trusted with the allocation, as it may claim far more pixels
+    // NB: than the data holds. Beyond the cap, the pixels only grow with those actually decoded.
+    let output_size = match streamed {
+        true => (width as usize).saturating_mul(height as usize),
+        false => image_size,
+    };
+    let mut pixels = Vec::with_capacity(output_size.min(MAX_PREALLOCATED_PIXELS));
+
+    // Decode each `QOI_OP`
+    let mut decoded = 0;
+    while decoded < image_size {
+        let (pixel, count) = state.decode_op(reader)?;
+
+        // NB: Runs are clamped to the remaining pixels of the image
+        let count = count.min(image_size - decoded);
+        let pixel = emit(pixel);
+
+        match streamed {
+            false => pixels.extend(std::iter::repeat_n(pixel, count)),
+            true => {
+                let kept = (decoded..decoded + count).filter(|&position| {
+                    let (x, y) = ((position % stride) as u32, (position / stride) as u32);
+                    placement.index(x, y).is_some()
+                });
+                pixels.extend(kept.map(|_| pixel));
+            }
+        }
+
+        decoded += count;
+    }
+
+    // Check the end marker
+    {
+        let mut end_marker = [0; 8];
+        reader.read_to_slice(&mut end_marker)?;
+
+        if &end_marker != QOI_END_MARKER {
+            return Err(Error::InvalidEndMarker(end_marker));
+        }
+    }
+
+    if streamed {
+        if options.flip_horizontal && width > 0 {
+            pixels
+                .chunks_exact_mut(width as usize)
+                .for_each(<[_]>::reverse);
+        }
+    } else {
+        // NB: Only done once every pixel is decoded, as the order is as large as the header claims
+        extensions
+            .traversal
+            .unpermute(&mut pixels, header.width(), header.height());
+
+        if placed {
+            let mut output = vec![Pixel::<N>::default(); width as usize * height as usize];
+            for (position, pixel) in pixels.into_iter().enumerate() {
+                let (x, y) = ((position % stride) as u32, (position / stride) as u32);
+                if let Some(index) = placement.index(x, y) {
+                    output[index] = pixel;
+                }
+            }
+            pixels = output;
+        }
+    }
+
+    let header = Header::new(width, height, header.channels(), header.color_space());
+
+    Ok((header, pixels))
+}
+
+/// State of a decoder between `QOI_OP`s
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct DecoderState {
+    /// Last decoded pixel
+    // NB: The decoder always keeps track of the alpha channel, as `QOI_OP_RGBA` may still appear
+    // NB: in a file with only 3 channels
+    pub(crate) previous_pixel: Pixel<4>,
+
+    /// A running "hash set" of all seen pixels
+    pub(crate) seen_pixels: [Pixel<4>; 64],
+
+    /// Profile of the image, selecting the `QOI_OP`s to decode
+    pub(crate) profile: Profile,
+
+    /// The larger running "hash set" of the extended profile, only updated in that profile
+    pub(crate) long_seen_pixels: [Pixel<4>; 256],
+}
+
+impl DecoderState {
+    /// State at the start of an image with the standard profile
+    pub(crate) fn new() -> Self {
+        Self::with_profile(Profile::Standard)
+    }
+
+    /// State at the start of an image with the given `profile`
+    pub(crate) fn with_profile(profile: Profile) -> Self {
+        Self {
+            previous_pixel: Pixel::new_initial(),
+            seen_pixels: [Pixel::default(); 64],
+            profile,
+            long_seen_pixels: [Pixel::default(); 256],
+        }
+    }
+
+    /// Decodes a single `QOI_OP` from the `reader`, returning the decoded pixel and the number of
+    /// times it repeats, which is only more than `1` for a `QOI_OP_RUN` or `QOI_OP_RUN_LONG`.
+    ///
+    /// Returns `Err` if reading fails.
+    pub(crate) fn decode_op(&mut self, reader: &mut impl Reader) -> Result<(Pixel<4>, usize)> {
+        let tag = reader.read_byte()?;
+        let extended = self.profile == Profile::Extended;
+
+        let pixel = match tag {
+            QOI_OP_RGB => {
+                let mut rgb = [0; 3];
+                reader.read_to_slice(&mut rgb)?;
+
+                Pixel::rgba(rgb[0], rgb[1], rgb[2], self.previous_pixel.alpha())
+            }
+
+            QOI_OP_RGBA => {
+                let mut rgba = [0; 4];
+                reader.read_to_slice(&mut rgba)?;
+
+                Pixel::<4>::from_inner_rgba(rgba)
+            }
+
+            // NB: In the standard profile, these tags are the longest `QOI_OP_RUN`s
+            QOI_OP_ALPHA if extended => {
+                let mut pixel = self.previous_pixel.as_inner_rgba();
+                pixel[3] = reader.read_byte()?;
+
+                Pixel::<4>::from_inner_rgba(pixel)
+            }
+
+            QOI_OP_RUN_LONG if extended => {
+                let mut length = [0; 2];
+                reader.read_to_slice(&mut length)?;
+
+                // NB: A long run is stored with a bias of `-1` as well
+                return Ok((self.previous_pixel, u16::from_be_bytes(length) as usize + 1));
+            }
+
+            QOI_OP_INDEX_LONG if extended => self.long_seen_pixels[reader.read_byte()? as usize],
+
+            _ => match tag & QOI_OP_MASK {
+                QOI_OP_INDEX => self.seen_pixels[tag as usize],
+
+                QOI_OP_DIFF => {
+                    // Remove the bias of `2` from each difference
+                    let diff_red = (tag >> 4 & 0b11).wrapping_sub(2);
+                    let diff_green = (tag >> 2 & 0b11).wrapping_sub(2);
+                    let diff_blue = (tag & 0b11).wrapping_sub(2);
+
+                    Pixel::rgba(
+                        self.previous_pixel.red().wrapping_add(diff_red),
+                        self.previous_pixel.green().wrapping_add(diff_green),
+                        self.previous_pixel.blue().wrapping_add(diff_blue),
+                        self.previous_pixel.alpha(),
+                    )
+                }
+
+                QOI_OP_LUMA => {
+                    let byte = reader.read_byte()?;
+
+                    // Remove the bias of `32` from `dg` and `8` from `dr_dg` and `db_dg`
+                    let diff_green = (tag & !QOI_OP_MASK).wrapping_sub(32);
+                    let diff_red_green = (byte >> 4).wrapping_sub(8);
+                    let diff_blue_green = (byte & 0b1111).wrapping_sub(8);
+
+                    Pixel::rgba(
+                        self.previous_pixel
+                            .red()
+                            .wrapping_add(diff_green)
+                            .wrapping_add(diff_red_green),
+                        self.previous_pixel.green().wrapping_add(diff_green),
+                        self.previous_pixel
+                            .blue()
+                            .wrapping_add(diff_green)
+                            .wrapping_add(diff_blue_green),
+                        self.previous_pixel.alpha(),
+                    )
+                }
+
+                QOI_OP_RUN => {
+                    // NB: A run is stored with a bias of `-1`
+                    return Ok((self.previous_pixel, (tag & !QOI_OP_MASK) as usize + 1));
+                }
+
+                _ => unreachable!(),
+            },
+        };
+
+        self.seen_pixels[pixel.index_hash()] = pixel;
+        if extended {
+            self.long_seen_pixels[pixel.long_index_hash()] = pixel;
+        }
+        self.previous_pixel = pixel;
+
+        Ok((pixel, 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        alpha::AlphaMode,
+        decode, decode_with_options, encode, encode_with_options,
+        header::{ColorChannel, ColorSpace, Header},
+        pixel::Pixel,
+        DecodeOptions, EncodeOptions, Error,
+    };
+
+    #[test]
+    fn can_decode_rgb() {
+        let bytes = [
+            0x71, 0x6f, 0x69, 0x66, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x03, 0x01,
+            0xfe, 0x64, 0x64, 0x64, 0xfe, 0xc8, 0xc8, 0xc8, 0xfe, 0x64, 0x65, 0x64, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+        ];
+
+        let (header, pixels) = decode::<3>(&mut &bytes[..]).unwrap();
+
+        assert_eq!(header.width(), 3);
+        assert_eq!(header.height(), 1);
+        assert_eq!(header.channels(), ColorChannel::Rgb);
+        assert_eq!(header.color_space(), ColorSpace::AllLinear);
+        assert_eq!(
+            pixels,
+            [
+                Pixel::rgb(100, 100, 100),
+                Pixel::rgb(200, 200, 200),
+                Pixel::rgb(100, 101, 100),
+            ]
+        );
+    }
+
+    #[test]
+    fn can_decode_all_ops() {
+        let bytes = [
+            0x71, 0x6f, 0x69, 0x66, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x03, 0x04, 0x01,
+            0xff, 0x64, 0x64, 0x64, 0x64, 0xff, 0xc8, 0xc8, 0xc8, 0xff, 0x28, 0xc5, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+        ];
+
+        let (_, pixels) = decode::<4>(&mut &bytes[..]).unwrap();
+
+        let mut expected = vec![Pixel::rgba(100, 100, 100, 100); 9];
+        expected[1] = Pixel::rgba(200, 200, 200, 255);
+
+        assert_eq!(pixels, expected);
+    }
+
+    #[test]
+    fn can_decode_diff_and_luma() {
+        let pixels = [
+            Pixel::rgb(25, 30, 35),
+            Pixel::rgb(20, 15, 3),
+            Pixel::rgb(36, 29, 17),
+            Pixel::rgb(33, 30, 25),
+            Pixel::rgb(34, 29, 25),
+            Pixel::rgb(0, 0, 0),
+            Pixel::rgb(255, 255, 255),
+            Pixel::rgb(0, 0, 0),
+        ];
+
+        let mut buf = vec![];
+        encode(&mut buf, &pixels, 4, 2, ColorSpace::Srgb).unwrap();
+
+        let (_, decoded) = decode::<3>(&mut buf.as_slice()).unwrap();
+
+        assert_eq!(decoded, pixels);
+    }
+
+    #[test]
+    fn can_decode_into_other_channels() {
+        let pixels = [Pixel::rgb(1, 2, 3), Pixel::rgb(4, 5, 6)];
+
+        let mut buf = vec![];
+        encode(&mut buf, &pixels, 2, 1, ColorSpace::Srgb).unwrap();
+
+        let (header, decoded) = decode::<4>(&mut buf.as_slice()).unwrap();
+
+        assert_eq!(header.channels(), ColorChannel::Rgb);
+        assert_eq!(
+            decoded,
+            [Pixel::rgba(1, 2, 3, 255), Pixel::rgba(4, 5, 6, 255)]
+        );
+    }
+
+    #[test]
+    fn decode_rejects_truncated_data() {
+        let pixels = [Pixel::rgb(1, 2, 3), Pixel::rgb(200, 5, 6)];
+
+        let mut buf = vec![];
+        encode(&mut buf, &pixels, 2, 1, ColorSpace::Srgb).unwrap();
+        buf.truncate(buf.len() - 10);
+
+        assert!(matches!(
+            decode::<3>(&mut buf.as_slice()),
+            Err(Error::IoError(_))
+        ));
+    }
+
+    #[test]
+    fn decode_rejects_invalid_end_marker() {
+        let pixels = [Pixel::rgb(1, 2, 3)];
+
+        let mut buf = vec![];
+        encode(&mut buf, &pixels, 1, 1, ColorSpace::Srgb).unwrap();
+        *buf.last_mut().unwrap() = 0x02;
+
+        assert!(matches!(
+            decode::<3>(&mut buf.as_slice()),
+            Err(Error::InvalidEndMarker([0, 0, 0, 0, 0, 0, 0, 2]))
+        ));
+    }
+
+    #[test]
+    fn premultiplied_pixels_round_trip() {
+        let pixels: Vec<_> = (0..=255u8)
+            .flat_map(|alpha| {
+                (0..=alpha)
+                    .step_by(7)
+                    .map(move |channel| Pixel::rgba(channel, alpha - channel, alpha, alpha))
+            })
+            .collect();
+
+        let options = EncodeOptions {
+            alpha: AlphaMode::Premultiplied,
+            ..Default::default()
+        };
+
+        let mut buf = vec![];
+        encode_with_options(
+            &mut buf,
+            &pixels,
+            pixels.len() as u32,
+            1,
+            ColorSpace::Srgb,
+            &options,
+        )
+        .unwrap();
+
+        let options = DecodeOptions {
+            alpha: AlphaMode::Premultiplied,
+            ..Default::default()
+        };
+
+        let (_, decoded) = decode_with_options::<4>(&mut buf.as_slice(), &options).unwrap();
+
+        assert_eq!(decoded, pixels);
+    }
+
+    #[test]
+    fn premultiplied_encoding_stores_straight_alpha() {
+        let pixels = [Pixel::rgba(64, 32, 0, 128)];
+
+        let options = EncodeOptions {
+            alpha: AlphaMode::Premultiplied,
+            ..Default::default()
+        };
+
+        let mut buf = vec![];
+        encode_with_options(&mut buf, &pixels, 1, 1, ColorSpace::Srgb, &options).unwrap();
+
+        let (_, decoded) = decode::<4>(&mut buf.as_slice()).unwrap();
+
+        assert_eq!(decoded, [Pixel::rgba(128, 64, 0, 128)]);
+    }
+
+    #[test]
+    fn encoding_converts_into_declared_color_space() {
+        let pixels = [Pixel::rgb(55, 0, 255)];
+
+        let options = EncodeOptions {
+            color_space: Some(ColorSpace::AllLinear),
+            ..Default::default()
+        };
+
+        let mut buf = vec![];
+        encode_with_options(&mut buf, &pixels, 1, 1, ColorSpace::Srgb, &options).unwrap();
+
+        let (header, decoded) = decode::<3>(&mut buf.as_slice()).unwrap();
+
+        assert_eq!(header.color_space(), ColorSpace::Srgb);
+        assert_eq!(decoded, [Pixel::rgb(128, 0, 255)]);
+    }
+
+    #[test]
+    fn decoding_converts_into_requested_color_space() {
+        let pixels = [Pixel::rgba(128, 0, 255, 128)];
+
+        let mut buf = vec![];
+        encode(&mut buf, &pixels, 1, 1, ColorSpace::Srgb).unwrap();
+
+        let options = DecodeOptions {
+            color_space: Some(ColorSpace::AllLinear),
+            ..Default::default()
+        };
+
+        let (header, decoded) = decode_with_options::<4>(&mut buf.as_slice(), &options).unwrap();
+
+        assert_eq!(header.color_space(), ColorSpace::Srgb);
+        assert_eq!(decoded, [Pixel::rgba(55, 0, 255, 128)]);
+
+        let options = DecodeOptions {
+            color_space: Some(ColorSpace::Srgb),
+            ..Default::default()
+        };
+
+        let (_, decoded) = decode_with_options::<4>(&mut buf.as_slice(), &options).unwrap();
+
+        assert_eq!(decoded, pixels);
+    }
+
+    #[test]
+    fn rejects_oversized_header() {
+        let mut buf = vec![];
+        encode(&mut buf, &[Pixel::rgb(1, 2, 3)], 1, 1, ColorSpace::Srgb).unwrap();
+        buf[4..12].fill(0xff);
+
+        assert!(matches!(
+            decode::<4>(&mut buf.as_slice()),
+            Err(Error::IoError(_))
+        ));
+    }
+
+    #[test]
+    fn checksum_is_verified_when_present() {
+        let pixels = [
+            Pixel::rgb(1, 2, 3),
+            Pixel::rgb(1, 2, 3),
+            Pixel::rgb(9, 8, 7),
+        ];
+        let encode_options = EncodeOptions {
+            checksum: true,
+            ..Default::default()
+        };
+        let verify = DecodeOptions {
+            verify_checksum: true,
+            ..Default::default()
+        };
+
+        let mut buf = vec![];
+        let written =
+            encode_with_options(&mut buf, &pixels, 3, 1, ColorSpace::Srgb, &encode_options)
+                .unwrap();
+        assert_eq!(written, buf.len());
+
+        // NB: Decoders unaware of the checksum stop at the end marker
+        assert_eq!(decode::<3>(&mut buf.as_slice()).unwrap().1, pixels);
+        assert_eq!(
+            decode_with_options::<3>(&mut buf.as_slice(), &verify)
+                .unwrap()
+                .1,
+            pixels
+        );
+
+        // NB: Flip a bit of the last `QOI_OP_RGB`, which still decodes
+        buf[Header::SIZE + 3] ^= 1;
+        assert!(decode::<3>(&mut buf.as_slice()).is_ok());
+        assert!(matches!(
+            decode_with_options::<3>(&mut buf.as_slice(), &verify),
+            Err(Error::InvalidChecksum { .. })
+        ));
+
+        // NB: Images without a checksum are accepted
+        let mut buf = vec![];
+        encode(&mut buf, &pixels, 3, 1, ColorSpace::Srgb).unwrap();
+        assert_eq!(
+            decode_with_options::<3>(&mut buf.as_slice(), &verify)
+                .unwrap()
+                .1,
+            pixels
+        );
+    }
+}
@@ -0,0 +1,226 @@
+//! Down-conversion of 16-bit per channel data into 8-bit [`Pixel`]s
+//!
+//! QOI only stores 8 bits per channel. [`down_convert`] implements the lossy step from 16-bit
+//! input, with the [`DownConversion`] method selecting how the precision is dropped. This is used
+//! by [`encode_u16`](crate::encode_u16), and is also available on its own.
+
+use crate::{
+    pixel::{Pixel, SupportedChannels},
+    Error, Result,
+};
+
+/// Method of converting a 16-bit channel into 8 bits
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DownConversion {
+    /// Keeps the high byte of each channel
+    Truncate,
+
+    /// Scales each channel to the nearest 8-bit value, ie. `round(v * 255 / 65535)`
+    #[default]
+    Round,
+
+    /// Thresholds the remainder of each channel against a 4x4 Bayer matrix
+    OrderedDither,
+
+    /// Diffuses the rounding error of each channel to its neighbours with Floyd-Steinberg weights
+    ErrorDiffusion,
+}
+
+/// A 4x4 Bayer matrix with thresholds from `0` to `15`
+const BAYER_4X4: [[u32; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Converts 16-bit per channel `data` of an image of `width` and `height` into 8-bit pixels, using
+/// `method` to drop the extra precision.
+///
+/// Each element of `data` is a pixel, ordered row by row like the pixels passed to
+/// [`encode`](crate::encode).
+///
+/// # Errors
+/// This function returns `Err` if the provided `width` and `height` differs from the length of
+/// `data` ([`Error::UnmatchedDataSize`]).
+pub fn down_convert<const N: usize>(
+    data: &[[u16; N]],
+    width: u32,
+    height: u32,
+    method: DownConversion,
+) -> Result<Vec<Pixel<N>>>
+where
+    Pixel<N>: SupportedChannels,
+{
+    let image_size = (width as usize).saturating_mul(height as usize);
+    if data.len() != image_size {
+        return Err(Error::UnmatchedDataSize {
+            data_size: data.len(),
+            header_size: image_size,
+        });
+    }
+
+    let pixels = match method {
+        DownConversion::Truncate => data
+            .iter()
+            .map(|pixel| to_pixel(pixel.map(|channel| (channel >> 8) as u8)))
+            .collect(),
+
+        DownConversion::Round => data
+            .iter()
+            .map(|pixel| to_pixel(pixel.map(round)))
+            .collect(),
+
+        DownConversion::OrderedDither => data
+            .iter()
+            .enumerate()
+            .map(|(i, pixel)| {
+                let x = i % width as usize;
+                let y = i / width as usize;
+
+                // NB: Thresholds are centered in each of the 16 levels of the matrix
+                let threshold = (BAYER_4X4[y % 4][x % 4] * 2 + 1) * 65535 / 32;
+
+                to_pixel(pixel.map(|channel| {
+                    let scaled = channel as u32 * 255;
+                    (scaled / 65535 + (scaled % 65535 >= threshold) as u32) as u8
+                }))
+            })
+            .collect(),
+
+        DownConversion::ErrorDiffusion => error_diffusion(data, width as usize),
+    };
+
+    Ok(pixels)
+}
+
+/// Scales a 16-bit `channel` to the nearest 8-bit value
+fn round(channel: u16) -> u8 {
+    ((channel as u32 * 255 + 32767) / 65535) as u8
+}
+
+fn to_pixel<const N: usize>(channels: [u8; N]) -> Pixel<N>
+where
+    Pixel<N>: SupportedChannels,
+{
+    let mut rgba = [255; 4];
+    rgba[..N].copy_from_slice(&channels);
+
+    Pixel::from_inner_rgba(rgba)
+}
+
+/// Floyd-Steinberg dithering of `data`, with errors kept in 16-bit units
+fn error_diffusion<const N: usize>(data: &[[u16; N]], width: usize) -> Vec<Pixel<N>>
+where
+    Pixel<N>: SupportedChannels,
+{
+    let mut pixels = Vec::with_capacity(data.len());
+    if width == 0 {
+        return pixels;
+    }
+
+    // Accumulated errors of the current and next row, padded by a pixel on both sides
+    let mut current_errors = vec![[0i32; N]; width + 2];
+    let mut next_errors = vec![[0i32; N]; width + 2];
+
+    for row in data.chunks(width) {
+        for (x, pixel) in row.iter().enumerate() {
+            let mut channels = [0; N];
+
+            for c in 0..N {
+                let target = (pixel[c] as i32 + current_errors[x + 1][c]).clamp(0, 65535);
+                channels[c] = round(target as u16);
+
+                // NB: `255 * 257 == 65535`, so this maps the 8-bit value back to 16 bits exactly
+                let error = target - channels[c] as i32 * 257;
+
+                current_errors[x + 2][c] += error * 7 / 16;
+                next_errors[x][c] += error * 3 / 16;
+                next_errors[x + 1][c] += error * 5 / 16;
+                next_errors[x + 2][c] += error / 16;
+            }
+
+            pixels.push(to_pixel(channels));
+        }
+
+        std::mem::swap(&mut current_errors, &mut next_errors);
+        next_errors.fill([0; N]);
+    }
+
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_keeps_high_byte() {
+        let data = [[0x12ff, 0xff00, 0x0080]];
+
+        assert_eq!(
+            down_convert(&data, 1, 1, DownConversion::Truncate).unwrap(),
+            [Pixel::rgb(0x12, 0xff, 0x00)]
+        );
+    }
+
+    #[test]
+    fn round_scales_to_nearest() {
+        let data = [[0, 65535, 128, 129]];
+
+        assert_eq!(
+            down_convert(&data, 1, 1, DownConversion::Round).unwrap(),
+            [Pixel::rgba(0, 255, 0, 1)]
+        );
+    }
+
+    #[test]
+    fn exact_values_are_preserved_by_all_methods() {
+        let data: Vec<_> = (0..=255u16).map(|v| [v * 257, v * 257, v * 257]).collect();
+        let expected: Vec<_> = (0..=255u8).map(|v| Pixel::rgb(v, v, v)).collect();
+
+        for method in [
+            DownConversion::Truncate,
+            DownConversion::Round,
+            DownConversion::OrderedDither,
+            DownConversion::ErrorDiffusion,
+        ] {
+            assert_eq!(
+                down_convert(&data, 16, 16, method).unwrap(),
+                expected,
+                "{method:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn dithering_preserves_average() {
+        // NB: Exactly halfway between 8-bit values `100` and `101`
+        let value = 100 * 257 + 128;
+        let data = vec![[value; 3]; 64 * 64];
+
+        for method in [
+            DownConversion::OrderedDither,
+            DownConversion::ErrorDiffusion,
+        ] {
+            let pixels = down_convert(&data, 64, 64, method).unwrap();
+            let sum: u32 = pixels.iter().map(|pixel| pixel.red() as u32).sum();
+
+            assert!(pixels
+                .iter()
+                .all(|pixel| (100..=101).contains(&pixel.red())));
+            assert!(
+                (sum as f64 / pixels.len() as f64 - 100.5).abs() < 0.05,
+                "{method:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn down_convert_rejects_unmatched_size() {
+        let data = [[0u16; 4]; 3];
+
+        assert!(matches!(
+            down_convert(&data, 2, 2, DownConversion::Round),
+            Err(Error::UnmatchedDataSize {
+                data_size: 3,
+                header_size: 4,
+            })
+        ));
+    }
+}
@@ -1,13 +1,68 @@
+use std::borrow::Cow;
+
 use crate::{
+    alpha::AlphaMode,
+    color_space,
     constants::{
-        QOI_END_MARKER, QOI_OP_DIFF, QOI_OP_INDEX, QOI_OP_LUMA, QOI_OP_RGB, QOI_OP_RGBA, QOI_OP_RUN,
+        QOI_END_MARKER, QOI_EXTENDED_MAX_RUN, QOI_MAX_LONG_RUN, QOI_MAX_RUN, QOI_OP_ALPHA,
+        QOI_OP_DIFF, QOI_OP_INDEX, QOI_OP_INDEX_LONG, QOI_OP_LUMA, QOI_OP_RGB, QOI_OP_RGBA,
+        QOI_OP_RUN, QOI_OP_RUN_LONG,
     },
+    crc::Checksummed,
+    depth::{self, DownConversion},
     header::{ColorChannel, ColorSpace, Header},
     io::Writer,
+    metadata::{self, Chunk},
+    near_lossless,
     pixel::{Pixel, SupportedChannels},
+    profile::{Extensions, Profile},
+    run::run_length,
+    transform::Transform,
+    traversal::Traversal,
     Error, Result,
 };
 
+/// Options controlling how [`encode_with_options`] interprets its input
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EncodeOptions {
+    /// Alpha representation of the input `pixels`, which is converted to straight alpha before
+    /// encoding. See [`crate::alpha`] for the rounding rules.
+    pub alpha: AlphaMode,
+
+    /// Color space of the input `pixels`. If it differs from the `color_space` declared in the
+    /// header, pixels are converted into the declared space before encoding. `None` assumes they
+    /// already are. See [`crate::color_space`] for the conversion used.
+    pub color_space: Option<ColorSpace>,
+
+    /// Maximum absolute error allowed on each channel of a decoded pixel. `0` keeps the encoding
+    /// lossless, while larger values let the encoder pick cheaper `QOI_OP`s for pixels that are
+    /// close enough. The output is still a standard QOI stream.
+    pub max_error: u8,
+
+    /// Whether to search for the smallest encoding instead of greedily picking the first `QOI_OP`
+    /// that fits, which is slower but never larger. Ignored when `max_error` is above `0`.
+    pub max_compression: bool,
+
+    /// Set of `QOI_OP`s to encode with. The extended profile is ignored when `max_error` is above
+    /// `0`. See [`crate::profile`].
+    pub profile: Profile,
+
+    /// Order in which the pixels are encoded, where [`Traversal::Auto`] tries each order and keeps
+    /// the smallest output. Ignored unless the extended `profile` is used. See
+    /// [`crate::traversal`].
+    pub traversal: Traversal,
+
+    /// Lossless transform of the colour channels applied before encoding. Ignored unless the
+    /// extended `profile` is used. See [`crate::transform`].
+    pub transform: Transform,
+
+    /// Whether to follow the end marker with a [`crate::metadata`] trailer holding a
+    /// [`Chunk::Checksum`] of the image, which [`DecodeOptions::verify_checksum`] validates.
+    ///
+    /// [`DecodeOptions::verify_checksum`]: crate::DecodeOptions::verify_checksum
+    pub checksum: bool,
+}
+
 /// Encodes the provided `pixels` data with `width`, `height` and `color_space` information into the
 /// QOI format, then writing it into the provided `writer`.
 ///
@@ -33,6 +88,63 @@ pub fn encode<const N: usize>(
     height: u32,
     color_space: ColorSpace,
 ) -> Result<usize>
+where
+    Pixel<N>: SupportedChannels,
+{
+    encode_with_options(
+        writer,
+        pixels,
+        width,
+        height,
+        color_space,
+        &EncodeOptions::default(),
+    )
+}
+
+/// Same as [`encode`], but with `options` controlling how `pixels` are interpreted.
+///
+/// # Errors
+/// See [`encode`].
+pub fn encode_with_options<const N: usize>(
+    writer: &mut impl Writer,
+    pixels: &[Pixel<N>],
+    width: u32,
+    height: u32,
+    color_space: ColorSpace,
+    options: &EncodeOptions,
+) -> Result<usize>
+where
+    Pixel<N>: SupportedChannels,
+{
+    if !options.checksum {
+        return encode_image(writer, pixels, width, height, color_space, options);
+    }
+
+    let mut checksummed = Checksummed::new(writer);
+    let mut written = encode_image(
+        &mut checksummed,
+        pixels,
+        width,
+        height,
+        color_space,
+        options,
+    )?;
+    let checksum = checksummed.finish();
+
+    written += metadata::write(writer, &[Chunk::Checksum(checksum)])?;
+
+    Ok(written)
+}
+
+/// Encodes a single image as described by [`encode_with_options`], without any trailer
+fn encode_image<const N: usize>(
+    writer: &mut impl Writer,
+    pixels: &[Pixel<N>],
+    width: u32,
+    height: u32,
+    color_space: ColorSpace,
+    options: &EncodeOptions,
+) -> Result<usize>
 where
     Pixel<N>: SupportedChannels,
 {
@@ -47,76 +159,363 @@ where
 
     let mut written = 0;
 
-    // Write header information
-    {
-        let channels = match N {
-            3 => ColorChannel::Rgb,
-            4 => ColorChannel::Rgba,
-            _ => unreachable!(),
+    // NB: The near-lossless op selection only emits `QOI_OP`s of the standard profile
+    let profile = match options.max_error {
+        0 => options.profile,
+        _ => Profile::Standard,
+    };
+
+    let header = header::<N>(width, height, color_space);
+
+    // Convert the input into straight alpha in the declared color space, borrowing the pixels as
+    // is when no conversion is needed
+    let input_color_space = options.color_space.unwrap_or(color_space);
+    let mut pixels: Cow<[Pixel<N>]> =
+        if options.alpha == AlphaMode::Straight && input_color_space == color_space {
+            Cow::Borrowed(pixels)
+        } else {
+            pixels
+                .iter()
+                .map(|&pixel| {
+                    color_space::convert(
+                        options.alpha.to_straight(pixel),
+                        input_color_space,
+                        color_space,
+                    )
+                })
+                .collect()
         };
 
-        let header = Header::new(width, height, channels, color_space);
-        written += writer.write_from_slice(&header.as_bytes())?;
+    // Hand over to the near-lossless op selection if any error is allowed
+    if options.max_error > 0 {
+        written +=
+            writer.write_from_slice(&profile.header_bytes(&header, Extensions::default()))?;
+        written += near_lossless::encode_ops(writer, pixels.iter().copied(), options.max_error)?;
+        written += writer.write_from_slice(QOI_END_MARKER)?;
+
+        return Ok(written);
+    }
+
+    // NB: Standard images are never transformed, and always traversed row by row
+    let transform = match profile {
+        Profile::Standard => Transform::Identity,
+        Profile::Extended => options.transform,
+    };
+    if transform != Transform::Identity {
+        pixels = pixels
+            .iter()
+            .map(|&pixel| transform.forward(pixel))
+            .collect();
     }
 
-    let mut previous_pixel = Pixel::<N>::new_initial();
+    let traversal = match (profile, options.traversal) {
+        (Profile::Standard, _) => Traversal::RowMajor,
+        (Profile::Extended, Traversal::Auto) => {
+            smallest_traversal(&pixels, width, height, profile, options.max_compression)?
+        }
+        (Profile::Extended, traversal) => traversal,
+    };
+
+    // Write header information, with the magic bytes of the profile
+    let extensions = Extensions {
+        traversal,
+        transform,
+    };
+    written += writer.write_from_slice(&profile.header_bytes(&header, extensions))?;
+
+    // Encode each pixel from the initial decoder state
+    written += encode_traversed(
+        writer,
+        &pixels,
+        width,
+        height,
+        traversal,
+        profile,
+        options.max_compression,
+    )?;
+
+    // Write the end marker
+    written += writer.write_from_slice(QOI_END_MARKER)?;
+
+    Ok(written)
+}
+
+/// Builds the header of an image with `N` channels
+pub(crate) fn header<const N: usize>(width: u32, height: u32, color_space: ColorSpace) -> Header {
+    let channels = match N {
+        3 => ColorChannel::Rgb,
+        4 => ColorChannel::Rgba,
+        _ => unreachable!(),
+    };
 
-    // A running "hash set" of all seen pixels
-    let mut seen_pixels = [Pixel::<4>::default(); 64];
+    Header::new(width, height, channels, color_space)
+}
+
+/// Encodes row-major `pixels` into `QOI_OP`s of `profile`, visiting them in `traversal`. Neither the
+/// header nor the end marker is written.
+///
+/// Returns the number of bytes written to the `writer`, or `Err` if writing fails.
+fn encode_traversed<const N: usize>(
+    writer: &mut impl Writer,
+    pixels: &[Pixel<N>],
+    width: u32,
+    height: u32,
+    traversal: Traversal,
+    profile: Profile,
+    max_compression: bool,
+) -> Result<usize>
+where
+    Pixel<N>: SupportedChannels,
+{
+    let pixels: Cow<[Pixel<N>]> = match traversal {
+        Traversal::RowMajor => Cow::Borrowed(pixels),
+        _ => Cow::Owned(traversal.permute(pixels, width, height)),
+    };
+
+    match max_compression {
+        true => encode_smallest_ops(writer, &pixels, profile),
+        false => encode_profile_ops(
+            writer,
+            &pixels,
+            Pixel::new_initial(),
+            [Pixel::<4>::default(); 64],
+            [Pixel::<4>::default(); 256],
+            profile,
+        ),
+    }
+}
+
+/// Finds the stored traversal of row-major `pixels` with the fewest `QOI_OP` bytes, preferring
+/// row-major on ties.
+fn smallest_traversal<const N: usize>(
+    pixels: &[Pixel<N>],
+    width: u32,
+    height: u32,
+    profile: Profile,
+    max_compression: bool,
+) -> Result<Traversal>
+where
+    Pixel<N>: SupportedChannels,
+{
+    // NB: Only the sizes are compared, so the winner is encoded once more into the actual writer
+    let mut smallest = (usize::MAX, Traversal::RowMajor);
+    for traversal in Traversal::STORED {
+        let size = encode_traversed(
+            &mut std::io::sink(),
+            pixels,
+            width,
+            height,
+            traversal,
+            profile,
+            max_compression,
+        )?;
+        if size < smallest.0 {
+            smallest = (size, traversal);
+        }
+    }
+
+    Ok(smallest.1)
+}
+
+/// Encodes `pixels` into `QOI_OP`s of the standard profile, starting from the state a decoder has
+/// after `previous_pixel` with `seen_pixels` in its running "hash set". Any run at the end of
+/// `pixels` is emitted, but neither the header nor the end marker is.
+///
+/// Returns the number of bytes written to the `writer`, or `Err` if writing fails.
+pub(crate) fn encode_ops<const N: usize>(
+    writer: &mut impl Writer,
+    pixels: &[Pixel<N>],
+    previous_pixel: Pixel<N>,
+    seen_pixels: [Pixel<4>; 64],
+) -> Result<usize>
+where
+    Pixel<N>: SupportedChannels,
+{
+    encode_profile_ops(
+        writer,
+        pixels,
+        previous_pixel,
+        seen_pixels,
+        [Pixel::<4>::default(); 256],
+        Profile::Standard,
+    )
+}
+
+/// Encodes a whole image of `pixels` into the smallest sequence of `QOI_OP`s of the `profile`,
+/// without the header and the end marker.
+///
+/// Any op decoding into a pixel leaves a decoder in the same state, with the pixel as the previous
+/// one and in its running "hash set", so picking the smallest op for every pixel as
+/// [`encode_profile_ops`] does is already optimal, runs included. The one exception is a run of
+/// the initial pixel at the start of the image, which never enters the "hash set". Emitting its
+/// first pixel as a `QOI_OP_DIFF` of zero instead costs at most one byte, but lets the initial
+/// pixel be indexed later on, so both are tried and the smaller one is kept.
+///
+/// Returns the number of bytes written to the `writer`, or `Err` if writing fails.
+fn encode_smallest_ops<const N: usize>(
+    writer: &mut impl Writer,
+    pixels: &[Pixel<N>],
+    profile: Profile,
+) -> Result<usize>
+where
+    Pixel<N>: SupportedChannels,
+{
+    let initial = Pixel::<N>::new_initial();
+
+    let mut greedy = vec![];
+    encode_profile_ops(
+        &mut greedy,
+        pixels,
+        initial,
+        [Pixel::<4>::default(); 64],
+        [Pixel::<4>::default(); 256],
+        profile,
+    )?;
+
+    if pixels.first() == Some(&initial) {
+        let mut seen_pixels = [Pixel::<4>::default(); 64];
+        seen_pixels[initial.index_hash()] = initial.as_rgba();
+        let mut long_seen_pixels = [Pixel::<4>::default(); 256];
+        if profile == Profile::Extended {
+            long_seen_pixels[initial.long_index_hash()] = initial.as_rgba();
+        }
+
+        // NB: Differences are stored with a bias of `2`
+        let mut indexed = vec![QOI_OP_DIFF | 2 << 4 | 2 << 2 | 2];
+        encode_profile_ops(
+            &mut indexed,
+            &pixels[1..],
+            initial,
+            seen_pixels,
+            long_seen_pixels,
+            profile,
+        )?;
+
+        if indexed.len() < greedy.len() {
+            return writer.write_from_slice(&indexed);
+        }
+    }
+
+    writer.write_from_slice(&greedy)
+}
+
+/// Same as [`encode_ops`], but with the `QOI_OP`s of the given `profile`, whose larger running
+/// "hash set" starts out with `long_seen_pixels` in the extended profile.
+fn encode_profile_ops<const N: usize>(
+    writer: &mut impl Writer,
+    pixels: &[Pixel<N>],
+    mut previous_pixel: Pixel<N>,
+    mut seen_pixels: [Pixel<4>; 64],
+    mut long_seen_pixels: [Pixel<4>; 256],
+    profile: Profile,
+) -> Result<usize>
+where
+    Pixel<N>: SupportedChannels,
+{
+    let extended = profile == Profile::Extended;
+
+    let mut written = 0;
 
     // Number of continuous run of the same pixel
-    let mut run = 0u8;
+    let mut run = 0;
 
-    /// A helper function that emits an `QOI_OP_RUN` with a provided `run` value to `w` and reset
-    /// `run`. This function returns `Err` if [`Writer::write_byte`] fails.
-    fn emit_qoi_op_run(w: &mut impl Writer, run: &mut u8) -> Result<usize> {
+    /// A helper function that emits `QOI_OP_RUN`s, or `QOI_OP_RUN_LONG`s in the extended profile,
+    /// standing for a provided `run` value to `w` and resets `run`. This function returns `Err` if
+    /// [`Writer::write_byte`] fails.
+    fn emit_qoi_op_run(w: &mut impl Writer, run: &mut usize, profile: Profile) -> Result<usize> {
         debug_assert!(*run > 0);
 
-        let written = w.write_byte(QOI_OP_RUN | (*run - 1))?;
-        *run = 0;
+        let mut written = 0;
+        while *run > 0 {
+            let length = match profile {
+                // NB: Up to two `QOI_OP_RUN`s are smaller than a `QOI_OP_RUN_LONG`
+                Profile::Extended if *run > 2 * QOI_EXTENDED_MAX_RUN => {
+                    let length = (*run).min(QOI_MAX_LONG_RUN);
+                    written += w.write_byte(QOI_OP_RUN_LONG)?;
+                    written += w.write_from_slice(&((length - 1) as u16).to_be_bytes())?;
+                    length
+                }
+                Profile::Extended => {
+                    let length = (*run).min(QOI_EXTENDED_MAX_RUN);
+                    written += w.write_byte(QOI_OP_RUN | (length - 1) as u8)?;
+                    length
+                }
+                // NB: Maximum possible run is `62`, so longer runs are emitted in chunks of `62`
+                Profile::Standard => {
+                    let length = (*run).min(QOI_MAX_RUN);
+                    written += w.write_byte(QOI_OP_RUN | (length - 1) as u8)?;
+                    length
+                }
+            };
+            *run -= length;
+        }
 
         Ok(written)
     }
 
     // Encode each pixel
-    for pixel in pixels {
-        // This is an evil hack to "break out of a block" as an alternative to unstable feature
-        // `label_break_value`
-        (|| -> Result<()> {
-            // Check if the previous pixel is the same
-            if *pixel == previous_pixel {
-                run += 1;
+    let mut i = 0;
+    while i < pixels.len() {
+        let pixel = pixels[i];
 
-                // NB: Maximum possible run is `62`
-                if run == 62 {
-                    written += emit_qoi_op_run(writer, &mut run)?;
-                }
+        // Check if the previous pixel is the same, consuming the whole run of same pixels at once
+        if pixel == previous_pixel {
+            let length = run_length(&pixels[i..], pixel);
+            i += length;
+            run += length;
 
-                return Ok(());
-            }
+            continue;
+        }
 
+        // This is an evil hack to "break out of a block" as an alternative to unstable feature
+        // `label_break_value`
+        (|| -> Result<()> {
             // Emit a QOI_OP_RUN if there is an existing run of same pixels
             // NB: This will **NOT** return early as the current `pixel` is not handled yet
             if run > 0 {
-                written += emit_qoi_op_run(writer, &mut run)?;
+                written += emit_qoi_op_run(writer, &mut run, profile)?;
             }
 
             // Calculate the index of the `pixel` with the special hash function
             let index = pixel.index_hash();
 
             // Check if the current `pixel` can be indexed in the array
-            if pixel.as_rgba() == seen_pixels[index] {
-                written += writer.write_byte(QOI_OP_INDEX | index as u8)?;
-                return Ok(());
-            }
+            let indexed = pixel.as_rgba() == seen_pixels[index];
 
             // Update the seem pixel
             seen_pixels[index] = pixel.as_rgba();
 
+            // The extended profile has a second, larger array, updated along with the first one
+            let (long_index, long_indexed) = match extended {
+                true => {
+                    let long_index = pixel.long_index_hash();
+                    let long_indexed = pixel.as_rgba() == long_seen_pixels[long_index];
+                    long_seen_pixels[long_index] = pixel.as_rgba();
+
+                    (long_index, long_indexed)
+                }
+                false => (0, false),
+            };
+
+            if indexed {
+                written += writer.write_byte(QOI_OP_INDEX | index as u8)?;
+                return Ok(());
+            }
+
             // If the alpha channel of the pixel is different, there is no choice but to emit a
-            // `QOI_OP_RGBA`
+            // `QOI_OP_RGBA`, unless the extended profile has a smaller op for it
             // NB: This only matters if there is alpha channel data, ie `N == 4`
             if N == 4 && pixel.alpha() != previous_pixel.alpha() {
+                if long_indexed {
+                    written += writer.write_from_slice(&[QOI_OP_INDEX_LONG, long_index as u8])?;
+                    return Ok(());
+                }
+
+                if extended && pixel.as_inner_rgb() == previous_pixel.as_inner_rgb() {
+                    written += writer.write_from_slice(&[QOI_OP_ALPHA, pixel.alpha()])?;
+                    return Ok(());
+                }
+
                 written += writer.write_byte(QOI_OP_RGBA)?;
                 written += writer.write_from_slice(&pixel.as_inner_rgba())?;
                 return Ok(());
@@ -143,6 +542,12 @@ where
                 }
             }
 
+            // NB: Past the single byte `QOI_OP_DIFF`, a `QOI_OP_INDEX_LONG` is as small as any op
+            if long_indexed {
+                written += writer.write_from_slice(&[QOI_OP_INDEX_LONG, long_index as u8])?;
+                return Ok(());
+            }
+
             // Calculate `dr_dg` and `db_dg` as by the specification
             let diff_red_green = diff_red.wrapping_sub(diff_green);
             let diff_blue_green = diff_blue.wrapping_sub(diff_green);
@@ -178,23 +583,43 @@ where
         })()?;
 
         // Update previous pixel
-        previous_pixel = *pixel;
+        previous_pixel = pixel;
+        i += 1;
     }
 
     // Emit a last `QOI_OP_RUN` if there is a remaining run at the end
     if run > 0 {
-        written += emit_qoi_op_run(writer, &mut run)?;
+        written += emit_qoi_op_run(writer, &mut run, profile)?;
     }
 
-    // Write the end marker
-    written += writer.write_from_slice(QOI_END_MARKER)?;
-
     Ok(written)
 }
 
+/// Same as [`encode_with_options`], but takes 16-bit per channel `data` which is first converted
+/// into 8-bit pixels with `down_conversion`. See [`depth::down_convert`] for the layout of `data`.
+///
+/// # Errors
+/// See [`encode`].
+pub fn encode_u16<const N: usize>(
+    writer: &mut impl Writer,
+    data: &[[u16; N]],
+    width: u32,
+    height: u32,
+    color_space: ColorSpace,
+    down_conversion: DownConversion,
+    options: &EncodeOptions,
+) -> Result<usize>
+where
+    Pixel<N>: SupportedChannels,
+{
+    let pixels = depth::down_convert(data, width, height, down_conversion)?;
+
+    encode_with_options(writer, &pixels, width, height, color_space, options)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{encode, header::ColorSpace, pixel::Pixel};
+    use crate::{encode, encode_u16, header::ColorSpace, pixel::Pixel, DownConversion};
 
     #[test]
     fn can_encode_rgb() {
@@ -417,4 +842,330 @@ mod tests {
             ],
         );
     }
+
+    #[test]
+    fn can_encode_u16() {
+        let data = [
+            [0x6464, 0x6464, 0x6464],
+            [0xc800, 0xc8ff, 0xc880],
+            [0x6400, 0x6500, 0x6400],
+        ];
+        let width = 3;
+        let height = 1;
+        let color_space = ColorSpace::AllLinear;
+
+        let mut buf = vec![];
+
+        let result = encode_u16(
+            &mut buf,
+            &data,
+            width,
+            height,
+            color_space,
+            DownConversion::Truncate,
+            &Default::default(),
+        );
+
+        assert!(matches!(result, Ok(34)), "result unmatched: {result:?}");
+
+        assert_eq!(
+            buf,
+            [
+                0x71, 0x6f, 0x69, 0x66, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x03, 0x01,
+                0xfe, 0x64, 0x64, 0x64, 0xfe, 0xc8, 0xc8, 0xc8, 0xfe, 0x64, 0x65, 0x64, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x01
+            ],
+        );
+    }
+
+    #[test]
+    fn can_encode_long_run() {
+        let mut pixels = vec![Pixel::rgb(127, 127, 127); 200];
+        pixels.extend([Pixel::rgb(0, 0, 0); 62]);
+        let width = 262;
+        let height = 1;
+        let color_space = ColorSpace::AllLinear;
+
+        let mut buf = vec![];
+
+        let result = encode(&mut buf, &pixels, width, height, color_space);
+
+        assert!(matches!(result, Ok(35)), "result unmatched: {result:?}");
+
+        assert_eq!(
+            buf,
+            [
+                0x71, 0x6f, 0x69, 0x66, 0x00, 0x00, 0x01, 0x06, 0x00, 0x00, 0x00, 0x01, 0x03, 0x01,
+                0xfe, 0x7f, 0x7f, 0x7f, 0xfd, 0xfd, 0xfd, 0xcc, 0xfe, 0x00, 0x00, 0x00, 0xfc, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01
+            ],
+        );
+    }
+}
+
+#[cfg(test)]
+mod round_trip_tests {
+    use proptest::prelude::*;
+
+    use crate::{
+        decode, encode_with_options,
+        header::ColorSpace,
+        pixel::{Pixel, SupportedChannels},
+        EncodeOptions, Profile, Transform, Traversal,
+    };
+
+    /// A step building the next pixels of an image from the previous one, each aimed at a
+    /// particular `QOI_OP` or one of its edge cases
+    #[derive(Clone, Debug)]
+    enum Step {
+        /// An unrelated pixel
+        New([u8; 4]),
+
+        /// Repeats the previous pixel, around the maximum length of a `QOI_OP_RUN` in either profile
+        Run(usize),
+
+        /// A pixel with the same `index_hash` as the previous one, but different channels
+        Collide(u8),
+
+        /// Changes only the alpha of the previous pixel
+        Alpha(u8),
+
+        /// Adds small wrapping differences to each colour channel of the previous pixel
+        Diff([i8; 3]),
+
+        /// Repeats a pixel from earlier in the image
+        Recall(usize),
+
+        /// The pixel a decoder starts out with as the previous one
+        Initial,
+    }
+
+    /// Channel values around the wrapping boundaries
+    fn edge_channel() -> impl Strategy<Value = u8> {
+        prop::sample::select(vec![0, 1, 2, 127, 128, 253, 254, 255])
+    }
+
+    fn step() -> impl Strategy<Value = Step> {
+        prop_oneof![
+            any::<[u8; 4]>().prop_map(Step::New),
+            prop::array::uniform4(edge_channel()).prop_map(Step::New),
+            prop::sample::select(vec![1, 2, 58, 59, 60, 61, 62, 63, 64, 123, 124, 125, 300])
+                .prop_map(Step::Run),
+            (1..4u8).prop_map(Step::Collide),
+            any::<u8>().prop_map(Step::Alpha),
+            prop::array::uniform3(-40..40i8).prop_map(Step::Diff),
+            any::<usize>().prop_map(Step::Recall),
+            Just(Step::Initial),
+        ]
+    }
+
+    fn build(steps: &[Step]) -> Vec<[u8; 4]> {
+        let mut pixels = vec![];
+
+        for step in steps {
+            let [r, g, b, a] = pixels.last().copied().unwrap_or([0, 0, 0, 255]);
+
+            match *step {
+                Step::New(pixel) => pixels.push(pixel),
+                Step::Run(length) => pixels.extend(std::iter::repeat_n([r, g, b, a], length)),
+                // NB: Adding multiples of `64` to any channel keeps `index_hash` the same
+                Step::Collide(multiple) => pixels.push([
+                    r.wrapping_add(64 * multiple),
+                    g,
+                    b.wrapping_add(64),
+                    a.wrapping_add(64 * (multiple - 1)),
+                ]),
+                Step::Alpha(alpha) => pixels.push([r, g, b, alpha]),
+                Step::Diff([dr, dg, db]) => pixels.push([
+                    r.wrapping_add_signed(dr),
+                    g.wrapping_add_signed(dg),
+                    b.wrapping_add_signed(db),
+                    a,
+                ]),
+                Step::Recall(index) if !pixels.is_empty() => {
+                    pixels.push(pixels[index % pixels.len()]);
+                }
+                Step::Recall(_) => {}
+                Step::Initial => pixels.push([0, 0, 0, 255]),
+            }
+        }
+
+        pixels
+    }
+
+    /// Images as a width and pixels whose length is a multiple of it
+    fn image() -> impl Strategy<Value = (u32, Vec<[u8; 4]>)> {
+        (prop::collection::vec(step(), 1..64), 1..32u32).prop_map(|(steps, width)| {
+            let mut pixels = build(&steps);
+            let width = width.min(pixels.len() as u32).max(1);
+            pixels.truncate(pixels.len() / width as usize * width as usize);
+
+            (width, pixels)
+        })
+    }
+
+    fn color_space() -> impl Strategy<Value = ColorSpace> {
+        prop_oneof![Just(ColorSpace::Srgb), Just(ColorSpace::AllLinear)]
+    }
+
+    fn profile() -> impl Strategy<Value = Profile> {
+        prop_oneof![Just(Profile::Standard), Just(Profile::Extended)]
+    }
+
+    fn traversal() -> impl Strategy<Value = Traversal> {
+        prop::sample::select(vec![
+            Traversal::RowMajor,
+            Traversal::ColumnMajor,
+            Traversal::Serpentine,
+            Traversal::Hilbert,
+            Traversal::Auto,
+        ])
+    }
+
+    fn transform() -> impl Strategy<Value = Transform> {
+        prop::sample::select(vec![
+            Transform::Identity,
+            Transform::SubtractGreen,
+            Transform::YCoCgR,
+        ])
+    }
+
+    fn options() -> impl Strategy<Value = EncodeOptions> {
+        (profile(), any::<bool>(), traversal(), transform()).prop_map(
+            |(profile, max_compression, traversal, transform)| EncodeOptions {
+                profile,
+                max_compression,
+                traversal,
+                transform,
+                ..Default::default()
+            },
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn rgb_round_trips(
+            (width, pixels) in image(),
+            color_space in color_space(),
+            options in options(),
+        ) {
+            let pixels: Vec<_> = pixels.iter().map(|&[r, g, b, _]| Pixel::rgb(r, g, b)).collect();
+            let height = pixels.len() as u32 / width;
+
+            let mut buf = vec![];
+            encode_with_options(&mut buf, &pixels, width, height, color_space, &options).unwrap();
+            let (header, decoded) = decode::<3>(&mut buf.as_slice()).unwrap();
+
+            prop_assert_eq!(header.color_space(), color_space);
+            prop_assert_eq!(decoded, pixels);
+        }
+
+        #[test]
+        fn rgba_round_trips(
+            (width, pixels) in image(),
+            color_space in color_space(),
+            options in options(),
+        ) {
+            let pixels: Vec<_> = pixels
+                .iter()
+                .map(|&[r, g, b, a]| Pixel::rgba(r, g, b, a))
+                .collect();
+            let height = pixels.len() as u32 / width;
+
+            let mut buf = vec![];
+            encode_with_options(&mut buf, &pixels, width, height, color_space, &options).unwrap();
+            let (header, decoded) = decode::<4>(&mut buf.as_slice()).unwrap();
+
+            prop_assert_eq!(header.color_space(), color_space);
+            prop_assert_eq!(decoded, pixels);
+        }
+
+        #[test]
+        fn max_compression_is_never_larger((width, pixels) in image(), profile in profile()) {
+            let pixels: Vec<_> = pixels
+                .iter()
+                .map(|&[r, g, b, a]| Pixel::rgba(r, g, b, a))
+                .collect();
+            let height = pixels.len() as u32 / width;
+            let greedy = EncodeOptions { profile, ..Default::default() };
+            let smallest = EncodeOptions { max_compression: true, ..greedy };
+
+            let mut greedy_buf = vec![];
+            encode_with_options(&mut greedy_buf, &pixels, width, height, ColorSpace::Srgb, &greedy)
+                .unwrap();
+            let mut smallest_buf = vec![];
+            encode_with_options(
+                &mut smallest_buf,
+                &pixels,
+                width,
+                height,
+                ColorSpace::Srgb,
+                &smallest,
+            )
+            .unwrap();
+
+            prop_assert!(smallest_buf.len() <= greedy_buf.len());
+            prop_assert_eq!(decode::<4>(&mut smallest_buf.as_slice()).unwrap().1, pixels);
+        }
+
+        #[test]
+        fn max_compression_matches_exhaustive_search(
+            pixels in prop::collection::vec(
+                (prop::sample::select(vec![0u8, 1, 255]), prop::sample::select(vec![0u8, 255]))
+                    .prop_map(|(c, a)| Pixel::rgba(c, c.wrapping_mul(2), 0, a)),
+                1..8,
+            ),
+        ) {
+            let options = EncodeOptions { max_compression: true, ..Default::default() };
+            let mut buf = vec![];
+            encode_with_options(&mut buf, &pixels, pixels.len() as u32, 1, ColorSpace::Srgb, &options)
+                .unwrap();
+
+            // NB: Header and end marker take `22` bytes
+            prop_assert_eq!(buf.len() - 22, smallest_size(&pixels, Pixel::new_initial(), [Pixel::default(); 64]));
+        }
+    }
+
+    /// Size of the smallest sequence of standard `QOI_OP`s decoding into `pixels`, found by trying
+    /// every op that fits the next pixel
+    fn smallest_size(pixels: &[Pixel<4>], previous: Pixel<4>, seen: [Pixel<4>; 64]) -> usize {
+        let Some(&pixel) = pixels.first() else {
+            return 0;
+        };
+
+        // Size of each op that decodes into `pixel`, leaving it in the "hash set"
+        let mut sizes = vec![5];
+        if seen[pixel.index_hash()] == pixel {
+            sizes.push(1);
+        }
+        if pixel.alpha() == previous.alpha() {
+            let diff = |c: fn(&Pixel<4>) -> u8| c(&pixel).wrapping_sub(c(&previous)) as i8 as i32;
+            let (dr, dg, db) = (diff(Pixel::red), diff(Pixel::green), diff(Pixel::blue));
+
+            sizes.push(4);
+            if [dr, dg, db].iter().all(|d| (-2..=1).contains(d)) {
+                sizes.push(1);
+            }
+            if (-32..=31).contains(&dg) && [dr - dg, db - dg].iter().all(|d| (-8..=7).contains(d)) {
+                sizes.push(2);
+            }
+        }
+
+        let mut next_seen = seen;
+        next_seen[pixel.index_hash()] = pixel;
+        let mut smallest =
+            sizes.iter().min().unwrap() + smallest_size(&pixels[1..], pixel, next_seen);
+
+        // NB: A run leaves the "hash set" as is
+        let run = pixels
+            .iter()
+            .take_while(|&&p| p == previous)
+            .count()
+            .min(62);
+        for length in 1..=run {
+            smallest = smallest.min(1 + smallest_size(&pixels[length..], previous, seen));
+        }
+
+        smallest
+    }
 }
@@ -1,12 +1,14 @@
 //! Errors for the library
 
+use crate::traversal::Traversal;
+
 /// A convenient short hand for `Result`s with our [`Error`] type
 pub type Result<T> = std::result::Result<T, Error>;
 
 /// Possible errors
 #[derive(Debug)]
 pub enum Error {
-    /// Did not find magic bytes `b"qoif"`
+    /// Did not find the expected magic bytes, ie. `b"qoif"` for a QOI image
     InvalidMagic([u8; 4]),
 
     /// Invalid number of channels
@@ -21,6 +23,49 @@ pub enum Error {
         header_size: usize,
     },
 
+    /// Did not find end marker `b"\x00\x00\x00\x00\x00\x00\x00\x01"` after the last pixel
+    InvalidEndMarker([u8; 8]),
+
+    /// Tiles of a tiled image have a zero `width` or `height`, or one is too large to be indexed
+    InvalidTileSize { width: u32, height: u32 },
+
+    /// Invalid disposal of an animation frame
+    InvalidDisposal(u8),
+
+    /// Invalid kind of a frame in a sequence
+    InvalidFrameKind(u8),
+
+    /// A metadata chunk of this kind is malformed or cannot be stored
+    InvalidChunk([u8; 4]),
+
+    /// Checksum stored in the data (`expected`) does not match the one computed (`actual`)
+    InvalidChecksum { expected: u32, actual: u32 },
+
+    /// A compressed image uses an unknown compressor, or one not enabled by the features of the
+    /// crate
+    UnsupportedCompression(u8),
+
+    /// The image is stored in a traversal that cannot be decoded this way, such as a thumbnail of
+    /// an image not stored row by row
+    UnsupportedTraversal(Traversal),
+
+    /// Invalid traversal ID in the upper bits of the color space byte of an extended header
+    InvalidTraversal(u8),
+
+    /// Invalid transform ID in the upper bits of the channels byte of an extended header
+    InvalidTransform(u8),
+
+    /// The requested region is not entirely within the image
+    InvalidRegion {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+
+    /// Checkpoints of a restart index are an invalid number of rows apart, ie. `0`
+    InvalidInterval(u32),
+
     /// Wrapper for `std::io::Error`
     IoError(std::io::Error),
 }
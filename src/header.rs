@@ -16,7 +16,7 @@ pub enum ColorSpace {
     AllLinear = 1,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Header {
     width: u32,
     height: u32,
@@ -25,7 +25,8 @@ pub struct Header {
 }
 
 impl Header {
-    pub(crate) const SIZE: usize = 14;
+    /// Size of an encoded header in bytes
+    pub const SIZE: usize = 14;
 
     pub(crate) fn new(
         width: u32,
@@ -41,7 +42,29 @@ impl Header {
         }
     }
 
-    pub(crate) fn from_bytes(bytes: [u8; 14]) -> Result<Self> {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn channels(&self) -> ColorChannel {
+        self.channels
+    }
+
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    /// Parses a header from its encoded `bytes`.
+    ///
+    /// # Errors
+    /// This function returns `Err` if the magic bytes, number of channels or color space are
+    /// invalid (see [`Error::InvalidMagic`], [`Error::InvalidChannelNumber`] and
+    /// [`Error::InvalidColorSpace`]).
+    pub fn from_bytes(bytes: [u8; Self::SIZE]) -> Result<Self> {
         if &bytes[0..4] != QOI_MAGIC {
             return Err(Error::InvalidMagic(bytes[0..4].try_into().unwrap()));
         }
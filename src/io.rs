@@ -15,3 +15,105 @@ impl<T: std::io::Write> Writer for T {
             .map_err(Error::IoError)
     }
 }
+
+pub trait Reader {
+    fn read_byte(&mut self) -> Result<u8> {
+        let mut byte = [0];
+        self.read_to_slice(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    fn read_to_slice(&mut self, bytes: &mut [u8]) -> Result<usize>;
+}
+
+impl<T: std::io::Read> Reader for T {
+    fn read_to_slice(&mut self, bytes: &mut [u8]) -> Result<usize> {
+        self.read_exact(bytes)
+            .map(|_| bytes.len())
+            .map_err(Error::IoError)
+    }
+}
+
+/// Maximum number of bytes allocated at once by [`read_untrusted`]
+const READ_BLOCK_SIZE: usize = 1 << 16;
+
+/// Reads `length` bytes from the `reader`, where `length` comes from the data itself.
+///
+/// NB: The length is not trusted with the allocation, so the bytes are read in blocks and the
+/// NB: buffer only grows with bytes actually read.
+pub(crate) fn read_untrusted(reader: &mut impl Reader, length: usize) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    while bytes.len() < length {
+        let start = bytes.len();
+        bytes.resize(start + (length - start).min(READ_BLOCK_SIZE), 0);
+        reader.read_to_slice(&mut bytes[start..])?;
+    }
+
+    Ok(bytes)
+}
+
+/// A [`Reader`] reading at most `remaining` bytes from `inner`, where reading past them fails as
+/// if the data ended early
+pub(crate) struct Take<'a, R> {
+    inner: &'a mut R,
+    remaining: u64,
+}
+
+impl<'a, R: Reader> Take<'a, R> {
+    pub(crate) fn new(inner: &'a mut R, limit: u64) -> Self {
+        Self {
+            inner,
+            remaining: limit,
+        }
+    }
+
+    /// Reads and discards the bytes left
+    pub(crate) fn skip_remaining(&mut self) -> Result<()> {
+        let mut block = [0; 1 << 12];
+        while self.remaining > 0 {
+            let length = (self.remaining as usize).min(block.len());
+            self.read_to_slice(&mut block[..length])?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Reader> Reader for Take<'_, R> {
+    fn read_to_slice(&mut self, bytes: &mut [u8]) -> Result<usize> {
+        if bytes.len() as u64 > self.remaining {
+            return Err(Error::IoError(std::io::ErrorKind::UnexpectedEof.into()));
+        }
+
+        let read = self.inner.read_to_slice(bytes)?;
+        self.remaining -= read as u64;
+
+        Ok(read)
+    }
+}
+
+/// A [`Reader`] counting the number of bytes read from `inner`
+pub(crate) struct Counting<'a, R> {
+    inner: &'a mut R,
+    count: u64,
+}
+
+impl<'a, R: Reader> Counting<'a, R> {
+    pub(crate) fn new(inner: &'a mut R) -> Self {
+        Self { inner, count: 0 }
+    }
+
+    /// Number of bytes read so far
+    pub(crate) fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+impl<R: Reader> Reader for Counting<'_, R> {
+    fn read_to_slice(&mut self, bytes: &mut [u8]) -> Result<usize> {
+        let read = self.inner.read_to_slice(bytes)?;
+        self.count += read as u64;
+
+        Ok(read)
+    }
+}
@@ -1,15 +1,47 @@
+pub mod alpha;
+pub mod animation;
+pub mod color_space;
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+pub mod compressed;
+pub mod depth;
 pub mod io;
+pub mod metadata;
+pub mod metrics;
+pub mod orientation;
+pub mod profile;
+pub mod quantize;
+pub mod restart;
+pub mod sequence;
+pub mod thumbnail;
+pub mod tiled;
+pub mod transform;
+pub mod traversal;
 
 mod constants;
+mod crc;
+mod decode;
 mod encode;
 mod error;
 mod header;
+mod near_lossless;
+mod parallel;
 mod pixel;
+mod run;
+mod slice_decode;
 
 #[macro_use]
 extern crate num_derive;
 
-pub use encode::encode;
+pub use alpha::AlphaMode;
+pub use decode::{decode, decode_with_options, DecodeOptions};
+pub use depth::DownConversion;
+pub use encode::{encode, encode_u16, encode_with_options, EncodeOptions};
 pub use error::{Error, Result};
 pub use header::{ColorChannel, ColorSpace, Header};
-pub use pixel::Pixel;
+pub use orientation::{Crop, Rotation};
+pub use parallel::encode_parallel;
+pub use pixel::{Pixel, SupportedChannels};
+pub use profile::Profile;
+pub use slice_decode::decode_from_slice;
+pub use transform::Transform;
+pub use traversal::Traversal;
@@ -1,3 +1,476 @@
-fn main() {
-    println!("Hello, world!");
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+    process::ExitCode,
+    time::Duration,
+};
+
+use qoi_rs::{
+    animation::{self, Animation, Disposal, Frame},
+    decode, encode,
+    metadata::{self, Chunk},
+    metrics,
+    thumbnail::{self, Filter},
+    ColorChannel, ColorSpace, Error, Header, Pixel, SupportedChannels,
+};
+
+type CliResult = Result<ExitCode, Box<dyn std::error::Error>>;
+
+/// Header, RGBA pixels and metadata chunks of a QOI image
+type ImageWithMetadata = (Header, Vec<Pixel<4>>, Vec<Chunk>);
+
+const USAGE: &str = "\
+Usage: qoi <command> [arguments]
+
+Commands:
+    convert <input> <output> [--checksum]
+        Converts between PNG and QOI images, picking the direction from the extension of <input>.
+        ICC profiles, EXIF data and text are carried across in the metadata trailer of QOI images,
+        along with a checksum of the QOI image if --checksum is given
+
+    verify <input.qoi>... [--checksum]
+        Decodes each image and validates the checksum in its metadata trailer if there is one,
+        exiting with a non-zero status if any fails. With --checksum, images without a checksum
+        fail as well
+
+    compare <a.qoi> <b.qoi> [--max-error <n>]
+        Prints MSE, PSNR, SSIM and maximum channel error between two images, exiting with a
+        non-zero status if the maximum error is above <n> (default: 0)
+
+    animate <output.qoia> <frames.qoi>... [--delay <ms>] [--loop <n>]
+        Builds an animation from full canvas frames, each shown for <ms> milliseconds
+        (default: 100), playing <n> times or forever if 0 (default: 0)
+
+    frames <input.qoia> <directory>
+        Extracts every frame of an animation as drawn on the canvas into
+        <directory>/frame-0000.qoi, <directory>/frame-0001.qoi, ...
+
+    thumbnail <input.qoi> <output.qoi> [--max <n>] [--filter <filter>]
+        Writes a thumbnail of <input.qoi> fitting within <n> pixels on either side (default: 256),
+        resampled row by row with box, bilinear or lanczos3 filtering (default: lanczos3). Images
+        of the extended profile must be stored row by row";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let result = match args.first().map(String::as_str) {
+        Some("convert") => convert(&args[1..]),
+        Some("verify") => verify(&args[1..]),
+        Some("compare") => compare(&args[1..]),
+        Some("animate") => animate(&args[1..]),
+        Some("frames") => frames(&args[1..]),
+        Some("thumbnail") => thumbnail(&args[1..]),
+        _ => {
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    result.unwrap_or_else(|err| {
+        eprintln!("error: {err}");
+        ExitCode::FAILURE
+    })
+}
+
+/// Reads and decodes the QOI image at `path` into RGBA pixels
+fn read_qoi(path: impl AsRef<Path>) -> Result<(Header, Vec<Pixel<4>>), Box<dyn std::error::Error>> {
+    let path = path.as_ref();
+    let mut reader = BufReader::new(File::open(path)?);
+
+    decode(&mut reader).map_err(|err| format!("{}: {err}", path.display()).into())
+}
+
+/// Parses the value following `flag` in `args`, returning `default` if the flag is absent
+fn parse_flag<T: std::str::FromStr>(args: &[String], flag: &str, default: T) -> Result<T, String> {
+    match args.iter().position(|arg| arg == flag) {
+        Some(i) => args
+            .get(i + 1)
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| format!("invalid or missing value for {flag}")),
+        None => Ok(default),
+    }
+}
+
+/// Flags that stand alone rather than take the argument following them as their value
+const SWITCHES: [&str; 1] = ["--checksum"];
+
+/// Returns the arguments that are neither flags nor the values of flags
+fn positional(args: &[String]) -> Vec<&str> {
+    let mut positional = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            positional.push(arg.as_str());
+        } else if !SWITCHES.contains(&arg.as_str()) {
+            args.next();
+        }
+    }
+
+    positional
+}
+
+/// Reads and decodes the QOI image at `path` into RGBA pixels along with its metadata, validating
+/// its checksum if the metadata has one
+fn read_qoi_with_metadata(
+    path: impl AsRef<Path>,
+) -> Result<ImageWithMetadata, Box<dyn std::error::Error>> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path)?;
+
+    let mut reader = bytes.as_slice();
+    let (header, pixels) =
+        decode(&mut reader).map_err(|err| format!("{}: {err}", path.display()))?;
+    let image = &bytes[..bytes.len() - reader.len()];
+    let chunks = metadata::read(&mut reader).map_err(|err| format!("{}: {err}", path.display()))?;
+
+    for chunk in &chunks {
+        if let Chunk::Checksum(expected) = *chunk {
+            let actual = metadata::checksum(image);
+            if actual != expected {
+                let err = qoi_rs::Error::InvalidChecksum { expected, actual };
+                return Err(format!("{}: {err}", path.display()).into());
+            }
+        }
+    }
+
+    Ok((header, pixels, chunks))
+}
+
+fn convert(args: &[String]) -> CliResult {
+    let positional = positional(args);
+    let [input, output, ..] = positional.as_slice() else {
+        return Err(USAGE.into());
+    };
+
+    let extension = Path::new(input).extension().and_then(|ext| ext.to_str());
+    match extension.map(str::to_ascii_lowercase).as_deref() {
+        Some("png") => png_to_qoi(input, output, args.iter().any(|arg| arg == "--checksum"))?,
+        Some("qoi") => qoi_to_png(input, output)?,
+        _ => return Err(format!("{input}: expected a .png or .qoi image").into()),
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn png_to_qoi(input: &str, output: &str, checksum: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let mut decoder = png::Decoder::new(BufReader::new(File::open(input)?));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+    let mut reader = decoder.read_info()?;
+    let size = reader
+        .output_buffer_size()
+        .ok_or_else(|| format!("{input}: image is too large"))?;
+    let mut buf = vec![0; size];
+    let info = reader.next_frame(&mut buf)?;
+    let bytes = &buf[..info.buffer_size()];
+
+    // NB: Text chunks may follow the image data, so the rest of the file is read first
+    reader.finish()?;
+    let png_info = reader.info();
+
+    let mut chunks = vec![];
+    if let Some(icc) = &png_info.icc_profile {
+        chunks.push(Chunk::Icc(icc.to_vec()));
+    }
+    if let Some(exif) = &png_info.exif_metadata {
+        chunks.push(Chunk::Exif(exif.to_vec()));
+    }
+    for text in &png_info.uncompressed_latin1_text {
+        chunks.push(Chunk::Text {
+            key: text.keyword.clone(),
+            value: text.text.clone(),
+        });
+    }
+    for text in &png_info.compressed_latin1_text {
+        chunks.push(Chunk::Text {
+            key: text.keyword.clone(),
+            value: text.get_text()?,
+        });
+    }
+    for text in &png_info.utf8_text {
+        chunks.push(Chunk::Text {
+            key: text.keyword.clone(),
+            value: text.get_text()?,
+        });
+    }
+
+    let mut image = vec![];
+    let (width, height) = (info.width, info.height);
+
+    // NB: Grayscale is expanded to RGB, as QOI only stores RGB and RGBA
+    match info.color_type {
+        png::ColorType::Grayscale => {
+            let pixels: Vec<_> = bytes.iter().map(|&l| Pixel::rgb(l, l, l)).collect();
+            encode(&mut image, &pixels, width, height, ColorSpace::Srgb)?;
+        }
+        png::ColorType::GrayscaleAlpha => {
+            let pixels: Vec<_> = bytes
+                .chunks_exact(2)
+                .map(|la| Pixel::rgba(la[0], la[0], la[0], la[1]))
+                .collect();
+            encode(&mut image, &pixels, width, height, ColorSpace::Srgb)?;
+        }
+        png::ColorType::Rgb => {
+            let pixels: Vec<_> = bytes
+                .chunks_exact(3)
+                .map(|rgb| Pixel::rgb(rgb[0], rgb[1], rgb[2]))
+                .collect();
+            encode(&mut image, &pixels, width, height, ColorSpace::Srgb)?;
+        }
+        png::ColorType::Rgba => {
+            let pixels: Vec<_> = bytes
+                .chunks_exact(4)
+                .map(|rgba| Pixel::rgba(rgba[0], rgba[1], rgba[2], rgba[3]))
+                .collect();
+            encode(&mut image, &pixels, width, height, ColorSpace::Srgb)?;
+        }
+        png::ColorType::Indexed => return Err(format!("{input}: unexpanded palette").into()),
+    }
+
+    if checksum {
+        chunks.push(Chunk::Checksum(metadata::checksum(&image)));
+    }
+
+    let mut writer = BufWriter::new(File::create(output)?);
+    writer.write_all(&image)?;
+    if !chunks.is_empty() {
+        metadata::write(&mut writer, &chunks)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn qoi_to_png(input: &str, output: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let (header, pixels, chunks) = read_qoi_with_metadata(input)?;
+
+    let mut info = png::Info::with_size(header.width(), header.height());
+    info.bit_depth = png::BitDepth::Eight;
+    let bytes: Vec<u8> = match header.channels() {
+        ColorChannel::Rgb => {
+            info.color_type = png::ColorType::Rgb;
+            pixels
+                .iter()
+                .flat_map(|pixel| [pixel.red(), pixel.green(), pixel.blue()])
+                .collect()
+        }
+        ColorChannel::Rgba => {
+            info.color_type = png::ColorType::Rgba;
+            pixels
+                .iter()
+                .flat_map(|pixel| [pixel.red(), pixel.green(), pixel.blue(), pixel.alpha()])
+                .collect()
+        }
+    };
+
+    for chunk in chunks {
+        match chunk {
+            Chunk::Icc(icc) => info.icc_profile = Some(icc.into()),
+            Chunk::Exif(exif) => info.exif_metadata = Some(exif.into()),
+            // NB: tEXt only holds Latin-1, anything else is kept in an iTXt
+            Chunk::Text { key, value } if value.chars().all(|c| (c as u32) < 0x100) => info
+                .uncompressed_latin1_text
+                .push(png::text_metadata::TEXtChunk::new(key, value)),
+            Chunk::Text { key, value } => info
+                .utf8_text
+                .push(png::text_metadata::ITXtChunk::new(key, value)),
+            Chunk::Checksum(_) | Chunk::Other { .. } => {}
+        }
+    }
+
+    let writer = BufWriter::new(File::create(output)?);
+    let mut writer = png::Encoder::with_info(writer, info)?.write_header()?;
+    writer.write_image_data(&bytes)?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+fn verify(args: &[String]) -> CliResult {
+    let inputs = positional(args);
+    if inputs.is_empty() {
+        return Err(USAGE.into());
+    }
+    let require_checksum = args.iter().any(|arg| arg == "--checksum");
+
+    let mut status = ExitCode::SUCCESS;
+    for input in inputs {
+        match read_qoi_with_metadata(input) {
+            Ok((_, _, chunks))
+                if require_checksum
+                    && !chunks
+                        .iter()
+                        .any(|chunk| matches!(chunk, Chunk::Checksum(_))) =>
+            {
+                eprintln!("{input}: no checksum");
+                status = ExitCode::FAILURE;
+            }
+            Ok(_) => println!("{input}: ok"),
+            Err(err) => {
+                eprintln!("{err}");
+                status = ExitCode::FAILURE;
+            }
+        }
+    }
+
+    Ok(status)
+}
+
+fn compare(args: &[String]) -> CliResult {
+    let positional = positional(args);
+    let [a, b, ..] = positional.as_slice() else {
+        return Err(USAGE.into());
+    };
+    let max_error: u8 = parse_flag(args, "--max-error", 0)?;
+
+    let (header_a, pixels_a) = read_qoi(a)?;
+    let (header_b, pixels_b) = read_qoi(b)?;
+
+    if (header_a.width(), header_a.height()) != (header_b.width(), header_b.height()) {
+        return Err(format!(
+            "dimensions differ: {}x{} and {}x{}",
+            header_a.width(),
+            header_a.height(),
+            header_b.width(),
+            header_b.height()
+        )
+        .into());
+    }
+
+    let (width, height) = (header_a.width(), header_a.height());
+    // NB: An RGB image compared against an RGBA one counts as fully opaque
+    let metrics = match (header_a.channels(), header_b.channels()) {
+        (ColorChannel::Rgb, ColorChannel::Rgb) => {
+            let rgb = |pixels: &[Pixel<4>]| -> Vec<_> {
+                pixels
+                    .iter()
+                    .map(|pixel| Pixel::rgb(pixel.red(), pixel.green(), pixel.blue()))
+                    .collect()
+            };
+            metrics::compare(&rgb(&pixels_a), &rgb(&pixels_b), width, height)?
+        }
+        _ => metrics::compare(&pixels_a, &pixels_b, width, height)?,
+    };
+
+    println!("MSE:       {:.6}", metrics.mse);
+    println!("PSNR:      {:.4} dB", metrics.psnr);
+    println!("SSIM:      {:.6}", metrics.ssim);
+    println!("Max error: {}", metrics.max_error);
+
+    if metrics.max_error > max_error {
+        eprintln!("maximum error {} is above {max_error}", metrics.max_error);
+        return Ok(ExitCode::FAILURE);
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn animate(args: &[String]) -> CliResult {
+    let positional = positional(args);
+    let [output, inputs @ ..] = positional.as_slice() else {
+        return Err(USAGE.into());
+    };
+    if inputs.is_empty() {
+        return Err(USAGE.into());
+    }
+
+    let delay = Duration::from_millis(parse_flag(args, "--delay", 100)?);
+    let loop_count = parse_flag(args, "--loop", 0)?;
+
+    let mut frames = vec![];
+    let mut color_space = None;
+    for input in inputs {
+        let (header, pixels) = read_qoi(input)?;
+        color_space.get_or_insert(header.color_space());
+
+        frames.push(Frame {
+            x: 0,
+            y: 0,
+            width: header.width(),
+            height: header.height(),
+            delay,
+            disposal: Disposal::None,
+            pixels,
+        });
+    }
+
+    // NB: The canvas is the size of the first frame, which every other frame has to fit in
+    let animation = Animation {
+        width: frames[0].width,
+        height: frames[0].height,
+        loop_count,
+        frames,
+    };
+
+    let mut writer = BufWriter::new(File::create(output)?);
+    animation::encode(&mut writer, &animation, color_space.unwrap())?;
+    writer.flush()?;
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn frames(args: &[String]) -> CliResult {
+    let [input, directory, ..] = args else {
+        return Err(USAGE.into());
+    };
+
+    let mut reader = BufReader::new(File::open(input)?);
+    let (animation, color_space) = animation::decode(&mut reader)?;
+    let Some(color_space) = color_space else {
+        return Err(format!("{input}: animation has no frames").into());
+    };
+
+    std::fs::create_dir_all(directory)?;
+    let mut i = 0;
+    animation.render(|canvas| {
+        let path = Path::new(directory).join(format!("frame-{i:04}.qoi"));
+        let mut writer = BufWriter::new(File::create(path).map_err(Error::IoError)?);
+        i += 1;
+
+        encode(
+            &mut writer,
+            canvas,
+            animation.width,
+            animation.height,
+            color_space,
+        )?;
+        writer.flush().map_err(Error::IoError)
+    })?;
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn thumbnail(args: &[String]) -> CliResult {
+    let positional = positional(args);
+    let [input, output, ..] = positional.as_slice() else {
+        return Err(USAGE.into());
+    };
+    let max_size = parse_flag(args, "--max", 256u32)?;
+    let filter = match parse_flag(args, "--filter", String::from("lanczos3"))?.as_str() {
+        "box" => Filter::Box,
+        "bilinear" => Filter::Bilinear,
+        "lanczos3" => Filter::Lanczos3,
+        filter => return Err(format!("unknown filter {filter}").into()),
+    };
+
+    let mut reader = BufReader::new(File::open(input)?);
+    let (header, pixels) = thumbnail::thumbnail::<4>(&mut reader, max_size, filter)
+        .map_err(|err| format!("{input}: {err}"))?;
+
+    let mut writer = BufWriter::new(File::create(output)?);
+    let (width, height, color_space) = (header.width(), header.height(), header.color_space());
+    match header.channels() {
+        ColorChannel::Rgb => {
+            let pixels: Vec<_> = pixels
+                .iter()
+                .map(|pixel| Pixel::rgb(pixel.red(), pixel.green(), pixel.blue()))
+                .collect();
+            encode(&mut writer, &pixels, width, height, color_space)?
+        }
+        ColorChannel::Rgba => encode(&mut writer, &pixels, width, height, color_space)?,
+    };
+    writer.flush()?;
+
+    Ok(ExitCode::SUCCESS)
 }
@@ -0,0 +1,276 @@
+//! Metadata stored in a trailer after the end marker of a QOI image
+//!
+//! Decoders stop reading at the end marker, so the trailer is ignored by anything unaware of it.
+//! It is written with [`write()`] right after [`encode`](crate::encode), and read with [`read`]
+//! right after [`decode`](crate::decode). Its layout is, with all integers in big-endian:
+//!
+//! | Field                          | Size in bytes  |
+//! |--------------------------------|----------------|
+//! | Magic bytes `b"qoim"`          | 4              |
+//! | Number of chunks               | 4              |
+//! | Chunks                         |                |
+//!
+//! Like in PNG, each chunk starts with its 4 byte kind and the length of its data as a `u32`,
+//! followed by the data and a CRC-32 of both the kind and the data.
+//!
+//! A [`Chunk::Checksum`] holds the CRC-32 of the whole image it follows, from the header up to and
+//! including the end marker, to detect corruption of the image itself. It is written and validated
+//! by [`EncodeOptions::checksum`](crate::EncodeOptions::checksum) and
+//! [`DecodeOptions::verify_checksum`](crate::DecodeOptions::verify_checksum), or with [`checksum`]
+//! alongside other chunks.
+
+use crate::{
+    crc::Crc32,
+    io::{read_untrusted, Reader, Writer},
+    Error, Result,
+};
+
+/// Magic bytes of a metadata trailer
+const METADATA_MAGIC: &[u8; 4] = b"qoim";
+
+const ICC_KIND: &[u8; 4] = b"ICCP";
+const EXIF_KIND: &[u8; 4] = b"EXIF";
+const TEXT_KIND: &[u8; 4] = b"TEXT";
+const CHECKSUM_KIND: &[u8; 4] = b"CSUM";
+
+/// A single piece of metadata
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Chunk {
+    /// An ICC color profile
+    Icc(Vec<u8>),
+
+    /// EXIF data, starting with its TIFF header
+    Exif(Vec<u8>),
+
+    /// Text under a `key`, stored as the UTF-8 `key`, a NUL byte and the UTF-8 `value`
+    Text { key: String, value: String },
+
+    /// CRC-32 of the preceding image, see [`checksum`]
+    Checksum(u32),
+
+    /// A chunk of an unknown `kind`, which is kept as is
+    Other { kind: [u8; 4], data: Vec<u8> },
+}
+
+impl Chunk {
+    /// The kind of the chunk as stored in the trailer
+    pub fn kind(&self) -> [u8; 4] {
+        match self {
+            Chunk::Icc(_) => *ICC_KIND,
+            Chunk::Exif(_) => *EXIF_KIND,
+            Chunk::Text { .. } => *TEXT_KIND,
+            Chunk::Checksum(_) => *CHECKSUM_KIND,
+            Chunk::Other { kind, .. } => *kind,
+        }
+    }
+
+    /// The data of the chunk as stored in the trailer
+    fn data(&self) -> Result<std::borrow::Cow<'_, [u8]>> {
+        Ok(match self {
+            Chunk::Icc(data) | Chunk::Exif(data) | Chunk::Other { data, .. } => data.into(),
+            Chunk::Text { key, value } => {
+                if key.contains('\0') {
+                    return Err(Error::InvalidChunk(*TEXT_KIND));
+                }
+
+                [key.as_bytes(), b"\0", value.as_bytes()].concat().into()
+            }
+            Chunk::Checksum(checksum) => checksum.to_be_bytes().to_vec().into(),
+        })
+    }
+
+    fn from_data(kind: [u8; 4], data: Vec<u8>) -> Result<Self> {
+        Ok(match &kind {
+            ICC_KIND => Chunk::Icc(data),
+            EXIF_KIND => Chunk::Exif(data),
+            TEXT_KIND => {
+                let text = String::from_utf8(data).map_err(|_| Error::InvalidChunk(kind))?;
+                let (key, value) = text.split_once('\0').ok_or(Error::InvalidChunk(kind))?;
+
+                Chunk::Text {
+                    key: key.into(),
+                    value: value.into(),
+                }
+            }
+            CHECKSUM_KIND => {
+                let bytes = data.try_into().map_err(|_| Error::InvalidChunk(kind))?;
+                Chunk::Checksum(u32::from_be_bytes(bytes))
+            }
+            _ => Chunk::Other { kind, data },
+        })
+    }
+}
+
+/// Computes the CRC-32 of an encoded `image`, from its header up to and including its end marker, as
+/// stored in a [`Chunk::Checksum`].
+pub fn checksum(image: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(image);
+    crc.finish()
+}
+
+/// Writes a trailer holding `chunks` into the provided `writer`, which should directly follow the
+/// end marker of an image.
+///
+/// The function returns the number of bytes written to the `writer`.
+///
+/// # Errors
+/// This function returns `Err` if either [`Writer::write_byte`] or [`Writer::write_from_slice`]
+/// fails, or a chunk cannot be stored ([`Error::InvalidChunk`]), ie. the `key` of a text chunk
+/// contains a NUL byte or the data is longer than [`u32::MAX`] bytes.
+pub fn write(writer: &mut impl Writer, chunks: &[Chunk]) -> Result<usize> {
+    let mut written = 0;
+
+    written += writer.write_from_slice(METADATA_MAGIC)?;
+    written += writer.write_from_slice(&(chunks.len() as u32).to_be_bytes())?;
+
+    for chunk in chunks {
+        let (kind, data) = (chunk.kind(), chunk.data()?);
+        let length = u32::try_from(data.len()).map_err(|_| Error::InvalidChunk(kind))?;
+
+        let mut crc = Crc32::new();
+        crc.update(&kind);
+        crc.update(&data);
+
+        written += writer.write_from_slice(&kind)?;
+        written += writer.write_from_slice(&length.to_be_bytes())?;
+        written += writer.write_from_slice(&data)?;
+        written += writer.write_from_slice(&crc.finish().to_be_bytes())?;
+    }
+
+    Ok(written)
+}
+
+/// Reads the trailer following the end marker of an image from the provided `reader`, returning
+/// its chunks. An image without a trailer, ie. where the `reader` ends right away, has no chunks.
+///
+/// # Errors
+/// This function returns `Err` in one of the following cases:
+///
+/// 1. Reading from the `reader` fails, including when the data ends early.
+/// 2. The magic bytes are invalid ([`Error::InvalidMagic`])
+/// 3. The CRC of a chunk does not match its kind and data ([`Error::InvalidChecksum`])
+/// 4. A text chunk is not valid UTF-8 or lacks the NUL byte after its `key`, or a checksum chunk
+///    is not 4 bytes long ([`Error::InvalidChunk`])
+pub fn read(reader: &mut impl Reader) -> Result<Vec<Chunk>> {
+    let mut magic = [0; 4];
+    match reader.read_byte() {
+        Ok(byte) => magic[0] = byte,
+        Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+            return Ok(vec![]);
+        }
+        Err(err) => return Err(err),
+    }
+    reader.read_to_slice(&mut magic[1..])?;
+    if &magic != METADATA_MAGIC {
+        return Err(Error::InvalidMagic(magic));
+    }
+
+    let count = read_u32(reader)?;
+
+    let mut chunks = vec![];
+    for _ in 0..count {
+        let mut kind = [0; 4];
+        reader.read_to_slice(&mut kind)?;
+        let length = read_u32(reader)? as usize;
+        let data = read_untrusted(reader, length)?;
+
+        let mut crc = Crc32::new();
+        crc.update(&kind);
+        crc.update(&data);
+
+        let expected = read_u32(reader)?;
+        let actual = crc.finish();
+        if actual != expected {
+            return Err(Error::InvalidChecksum { expected, actual });
+        }
+
+        chunks.push(Chunk::from_data(kind, data)?);
+    }
+
+    Ok(chunks)
+}
+
+fn read_u32(reader: &mut impl Reader) -> Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_to_slice(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode, encode, header::ColorSpace, pixel::Pixel};
+
+    fn chunks() -> Vec<Chunk> {
+        vec![
+            Chunk::Icc(vec![1, 2, 3, 4]),
+            Chunk::Exif(b"MM\0*\0\0\0\x08".to_vec()),
+            Chunk::Text {
+                key: "Software".into(),
+                value: "qoi-rs ✓".into(),
+            },
+            Chunk::Other {
+                kind: *b"xTRA",
+                data: vec![],
+            },
+        ]
+    }
+
+    #[test]
+    fn round_trips_after_image() {
+        let pixels = [Pixel::rgb(1, 2, 3), Pixel::rgb(4, 5, 6)];
+
+        let mut buf = vec![];
+        encode(&mut buf, &pixels, 2, 1, ColorSpace::Srgb).unwrap();
+        let image_size = buf.len();
+        write(&mut buf, &chunks()).unwrap();
+
+        // NB: Decoders stop at the end marker, leaving the trailer to be read
+        let mut reader = buf.as_slice();
+        let (_, decoded) = decode::<3>(&mut reader).unwrap();
+        assert_eq!(decoded, pixels);
+        assert_eq!(read(&mut reader).unwrap(), chunks());
+
+        let mut reader = &buf[..image_size];
+        decode::<3>(&mut reader).unwrap();
+        assert_eq!(read(&mut reader).unwrap(), []);
+    }
+
+    #[test]
+    fn rejects_corrupted_chunk() {
+        let mut buf = vec![];
+        write(&mut buf, &chunks()).unwrap();
+
+        // NB: Flip a byte of the ICC profile
+        buf[4 + 4 + 8] ^= 1;
+
+        assert!(matches!(
+            read(&mut buf.as_slice()),
+            Err(Error::InvalidChecksum { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_text() {
+        let chunk = Chunk::Text {
+            key: "a\0b".into(),
+            value: "c".into(),
+        };
+        assert!(matches!(
+            write(&mut vec![], &[chunk]),
+            Err(Error::InvalidChunk(kind)) if &kind == TEXT_KIND
+        ));
+
+        let chunk = Chunk::Other {
+            kind: *TEXT_KIND,
+            data: b"no separator".to_vec(),
+        };
+        let mut buf = vec![];
+        write(&mut buf, &[chunk]).unwrap();
+        assert!(matches!(
+            read(&mut buf.as_slice()),
+            Err(Error::InvalidChunk(kind)) if &kind == TEXT_KIND
+        ));
+    }
+}
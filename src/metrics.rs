@@ -0,0 +1,206 @@
+//! Image quality metrics between two images of equal dimensions
+//!
+//! Used to evaluate lossy preprocessing, such as the near-lossless mode of
+//! [`encode_with_options`](crate::encode_with_options), by comparing an original image against its
+//! round-tripped version. All metrics are computed over every channel of [`Pixel<N>`], so alpha is
+//! included for `N == 4`.
+
+use crate::{
+    pixel::{Pixel, SupportedChannels},
+    Error, Result,
+};
+
+/// Side length of the square windows SSIM is computed over
+const SSIM_WINDOW: usize = 8;
+
+/// Metrics describing the difference between two images
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Metrics {
+    /// Mean squared error over all channels
+    pub mse: f64,
+
+    /// Peak signal-to-noise ratio in decibels, which is infinite for identical images
+    pub psnr: f64,
+
+    /// Mean structural similarity index over all channels, where `1.0` means identical
+    pub ssim: f64,
+
+    /// Maximum absolute difference of any channel
+    pub max_error: u8,
+}
+
+/// Compares two images `a` and `b`, both of `width` and `height`.
+///
+/// SSIM is computed over non-overlapping 8x8 windows of each channel (smaller at the right and
+/// bottom edges) with the usual constants `C1 = (0.01 * 255)^2` and `C2 = (0.03 * 255)^2`, then
+/// averaged.
+///
+/// # Errors
+/// This function returns `Err` if the provided `width` and `height` differs from the length of
+/// either `a` or `b` ([`Error::UnmatchedDataSize`]).
+pub fn compare<const N: usize>(
+    a: &[Pixel<N>],
+    b: &[Pixel<N>],
+    width: u32,
+    height: u32,
+) -> Result<Metrics>
+where
+    Pixel<N>: SupportedChannels,
+{
+    let image_size = (width as usize).saturating_mul(height as usize);
+    for pixels in [a, b] {
+        if pixels.len() != image_size {
+            return Err(Error::UnmatchedDataSize {
+                data_size: pixels.len(),
+                header_size: image_size,
+            });
+        }
+    }
+
+    let channel = |pixel: &Pixel<N>, c: usize| pixel.as_inner_rgba()[c] as f64;
+
+    let mut squared_error = 0.0;
+    let mut max_error = 0;
+
+    for (a, b) in a.iter().zip(b) {
+        for (a, b) in a.as_inner_rgba().into_iter().zip(b.as_inner_rgba()).take(N) {
+            let error = a.abs_diff(b);
+
+            squared_error += (error as f64).powi(2);
+            max_error = max_error.max(error);
+        }
+    }
+
+    let samples = (image_size * N) as f64;
+    let mse = if image_size == 0 {
+        0.0
+    } else {
+        squared_error / samples
+    };
+    let psnr = 10.0 * (255.0f64.powi(2) / mse).log10();
+
+    // Compute SSIM for each window of each channel
+    let (width, height) = (width as usize, height as usize);
+    let mut ssim_sum = 0.0;
+    let mut windows = 0;
+
+    for window_y in (0..height).step_by(SSIM_WINDOW) {
+        for window_x in (0..width).step_by(SSIM_WINDOW) {
+            let indices = (window_y..(window_y + SSIM_WINDOW).min(height)).flat_map(|y| {
+                (window_x..(window_x + SSIM_WINDOW).min(width)).map(move |x| y * width + x)
+            });
+
+            for c in 0..N {
+                ssim_sum += window_ssim(
+                    indices
+                        .clone()
+                        .map(|i| (channel(&a[i], c), channel(&b[i], c))),
+                );
+                windows += 1;
+            }
+        }
+    }
+
+    let ssim = if windows == 0 {
+        1.0
+    } else {
+        ssim_sum / windows as f64
+    };
+
+    Ok(Metrics {
+        mse,
+        psnr,
+        ssim,
+        max_error,
+    })
+}
+
+/// Computes SSIM of a single window from pairs of samples
+fn window_ssim(samples: impl Iterator<Item = (f64, f64)> + Clone) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    let count = samples.clone().count() as f64;
+    let (sum_a, sum_b) = samples
+        .clone()
+        .fold((0.0, 0.0), |(sum_a, sum_b), (a, b)| (sum_a + a, sum_b + b));
+    let (mean_a, mean_b) = (sum_a / count, sum_b / count);
+
+    let (variance_a, variance_b, covariance) =
+        samples.fold((0.0, 0.0, 0.0), |(var_a, var_b, cov), (a, b)| {
+            let (a, b) = (a - mean_a, b - mean_b);
+            (var_a + a * a, var_b + b * b, cov + a * b)
+        });
+    let (variance_a, variance_b, covariance) =
+        (variance_a / count, variance_b / count, covariance / count);
+
+    ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+        / ((mean_a * mean_a + mean_b * mean_b + C1) * (variance_a + variance_b + C2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: usize, height: usize) -> Vec<Pixel<3>> {
+        (0..width * height)
+            .map(|i| Pixel::rgb((i % width) as u8, (i / width) as u8, 128))
+            .collect()
+    }
+
+    #[test]
+    fn identical_images() {
+        let pixels = gradient(20, 10);
+        let metrics = compare(&pixels, &pixels, 20, 10).unwrap();
+
+        assert_eq!(metrics.mse, 0.0);
+        assert_eq!(metrics.psnr, f64::INFINITY);
+        assert!((metrics.ssim - 1.0).abs() < 1e-12);
+        assert_eq!(metrics.max_error, 0);
+    }
+
+    #[test]
+    fn constant_offset() {
+        let a = gradient(20, 10);
+        let b: Vec<_> = a
+            .iter()
+            .map(|pixel| Pixel::rgb(pixel.red() + 2, pixel.green(), pixel.blue()))
+            .collect();
+
+        let metrics = compare(&a, &b, 20, 10).unwrap();
+
+        // NB: One of the three channels is off by `2` everywhere
+        assert!((metrics.mse - 4.0 / 3.0).abs() < 1e-12);
+        assert!((metrics.psnr - 46.88).abs() < 0.01);
+        assert!(metrics.ssim < 1.0 && metrics.ssim > 0.9, "{}", metrics.ssim);
+        assert_eq!(metrics.max_error, 2);
+    }
+
+    #[test]
+    fn inverted_images_have_low_ssim() {
+        let a = gradient(16, 16);
+        let b: Vec<_> = a
+            .iter()
+            .map(|pixel| Pixel::rgb(255 - pixel.red(), 255 - pixel.green(), pixel.blue()))
+            .collect();
+
+        let metrics = compare(&a, &b, 16, 16).unwrap();
+
+        assert!(metrics.ssim < 0.5, "{}", metrics.ssim);
+        assert_eq!(metrics.max_error, 255);
+    }
+
+    #[test]
+    fn compare_rejects_unmatched_size() {
+        let a = gradient(4, 4);
+        let b = gradient(4, 3);
+
+        assert!(matches!(
+            compare(&a, &b, 4, 4),
+            Err(Error::UnmatchedDataSize {
+                data_size: 12,
+                header_size: 16,
+            })
+        ));
+    }
+}
@@ -0,0 +1,314 @@
+//! Near-lossless selection of `QOI_OP`s
+//!
+//! Instead of requiring an exact match, each pixel may be replaced by any pixel within
+//! `max_error` of it on every channel (including alpha), as long as that lets a cheaper `QOI_OP`
+//! be emitted. The encoder mirrors the state of a decoder, ie. the previously *decoded* pixel and
+//! the running index of decoded pixels, so errors never accumulate and the output stays a
+//! standard QOI stream.
+
+use crate::{
+    constants::{QOI_OP_DIFF, QOI_OP_INDEX, QOI_OP_LUMA, QOI_OP_RGB, QOI_OP_RGBA, QOI_OP_RUN},
+    io::Writer,
+    pixel::{Pixel, SupportedChannels},
+    Result,
+};
+
+/// Encodes `pixels` into `QOI_OP`s written to `writer`, allowing each channel of a decoded pixel
+/// to differ by at most `max_error` from the input.
+///
+/// Returns the number of bytes written, excluding the header and end marker.
+pub(crate) fn encode_ops<const N: usize>(
+    writer: &mut impl Writer,
+    pixels: impl Iterator<Item = Pixel<N>>,
+    max_error: u8,
+) -> Result<usize>
+where
+    Pixel<N>: SupportedChannels,
+{
+    let mut written = 0;
+
+    // The pixel a decoder would have produced last
+    let mut previous_pixel = Pixel::<N>::new_initial().as_rgba();
+
+    // A running "hash set" of all decoded pixels, as seen by a decoder
+    let mut seen_pixels = [Pixel::<4>::default(); 64];
+
+    // Number of continuous run of the same decoded pixel
+    let mut run = 0u8;
+
+    for pixel in pixels {
+        let pixel = pixel.as_rgba();
+        let is_close = |candidate: Pixel<4>| {
+            candidate
+                .as_inner_rgba()
+                .iter()
+                .zip(pixel.as_inner_rgba())
+                .all(|(&a, b)| a.abs_diff(b) <= max_error)
+        };
+
+        // Extend a run if the previous pixel is close enough
+        if is_close(previous_pixel) {
+            run += 1;
+
+            // NB: Maximum possible run is `62`
+            if run == 62 {
+                written += writer.write_byte(QOI_OP_RUN | (run - 1))?;
+                run = 0;
+            }
+
+            continue;
+        }
+
+        if run > 0 {
+            written += writer.write_byte(QOI_OP_RUN | (run - 1))?;
+            run = 0;
+        }
+
+        let decoded = if let Some(index) = find_index(&seen_pixels, pixel, is_close) {
+            written += writer.write_byte(QOI_OP_INDEX | index as u8)?;
+            seen_pixels[index]
+        } else {
+            let (op, decoded) = diff_op(previous_pixel, pixel);
+
+            if is_close(decoded) {
+                written += writer.write_byte(op)?;
+                decoded
+            } else {
+                let (count, decoded) = emit_luma_or_rgb(writer, previous_pixel, pixel, &is_close)?;
+                written += count;
+                decoded
+            }
+        };
+
+        // NB: A decoder updates the index with every decoded pixel
+        seen_pixels[decoded.index_hash()] = decoded;
+        previous_pixel = decoded;
+    }
+
+    if run > 0 {
+        written += writer.write_byte(QOI_OP_RUN | (run - 1))?;
+    }
+
+    Ok(written)
+}
+
+/// Finds an index of `seen_pixels` that holds a pixel close to `pixel`, preferring an exact match.
+///
+/// NB: Only slots holding a pixel of their own hash are considered. Any other slot was never
+/// NB: written, and decoders that also update the index on `QOI_OP_RUN`, such as the reference
+/// NB: one, may hold a different pixel there.
+fn find_index(
+    seen_pixels: &[Pixel<4>; 64],
+    pixel: Pixel<4>,
+    is_close: impl Fn(Pixel<4>) -> bool,
+) -> Option<usize> {
+    let index = pixel.index_hash();
+    if seen_pixels[index] == pixel {
+        return Some(index);
+    }
+
+    seen_pixels
+        .iter()
+        .enumerate()
+        .position(|(i, &seen)| seen.index_hash() == i && is_close(seen))
+}
+
+/// Clamps the difference of `pixel` from `previous` into the range of a `QOI_OP_DIFF`, returning
+/// the op and the pixel it decodes to
+fn diff_op(previous: Pixel<4>, pixel: Pixel<4>) -> (u8, Pixel<4>) {
+    let [diff_red, diff_green, diff_blue] = clamped_diffs(previous, pixel, -2, 1);
+
+    // Bias the differences by `2`
+    let op = QOI_OP_DIFF
+        | ((diff_red + 2) as u8) << 4
+        | ((diff_green + 2) as u8) << 2
+        | (diff_blue + 2) as u8;
+
+    (op, apply_diffs(previous, [diff_red, diff_green, diff_blue]))
+}
+
+/// Emits either a `QOI_OP_LUMA` if it decodes close to `pixel`, or a `QOI_OP_RGB` / `QOI_OP_RGBA`
+/// otherwise. Returns the number of bytes written and the decoded pixel.
+fn emit_luma_or_rgb(
+    writer: &mut impl Writer,
+    previous: Pixel<4>,
+    pixel: Pixel<4>,
+    is_close: &impl Fn(Pixel<4>) -> bool,
+) -> Result<(usize, Pixel<4>)> {
+    // Attempt to use `QOI_OP_LUMA`
+    {
+        let [_, diff_green, _] = clamped_diffs(previous, pixel, -32, 31);
+
+        let diff_red_green = (pixel.red().wrapping_sub(previous.red()) as i8)
+            .wrapping_sub(diff_green)
+            .clamp(-8, 7);
+        let diff_blue_green = (pixel.blue().wrapping_sub(previous.blue()) as i8)
+            .wrapping_sub(diff_green)
+            .clamp(-8, 7);
+
+        let decoded = apply_diffs(
+            previous,
+            [
+                diff_green + diff_red_green,
+                diff_green,
+                diff_green + diff_blue_green,
+            ],
+        );
+
+        if is_close(decoded) {
+            let written = writer.write_from_slice(&[
+                QOI_OP_LUMA | (diff_green + 32) as u8,
+                ((diff_red_green + 8) as u8) << 4 | (diff_blue_green + 8) as u8,
+            ])?;
+
+            return Ok((written, decoded));
+        }
+    }
+
+    // Keep the previous alpha with a `QOI_OP_RGB` if it is close enough
+    let decoded = Pixel::rgba(pixel.red(), pixel.green(), pixel.blue(), previous.alpha());
+    if is_close(decoded) {
+        let mut written = writer.write_byte(QOI_OP_RGB)?;
+        written += writer.write_from_slice(&pixel.as_inner_rgb())?;
+
+        return Ok((written, decoded));
+    }
+
+    let mut written = writer.write_byte(QOI_OP_RGBA)?;
+    written += writer.write_from_slice(&pixel.as_inner_rgba())?;
+
+    Ok((written, pixel))
+}
+
+/// Calculates the wrapping difference of each colour channel of `pixel` from `previous`, clamped
+/// into `min..=max`
+fn clamped_diffs(previous: Pixel<4>, pixel: Pixel<4>, min: i8, max: i8) -> [i8; 3] {
+    [
+        pixel.red().wrapping_sub(previous.red()),
+        pixel.green().wrapping_sub(previous.green()),
+        pixel.blue().wrapping_sub(previous.blue()),
+    ]
+    .map(|diff| (diff as i8).clamp(min, max))
+}
+
+/// Applies wrapping differences to the colour channels of `previous`
+fn apply_diffs(previous: Pixel<4>, [diff_red, diff_green, diff_blue]: [i8; 3]) -> Pixel<4> {
+    Pixel::rgba(
+        previous.red().wrapping_add(diff_red as u8),
+        previous.green().wrapping_add(diff_green as u8),
+        previous.blue().wrapping_add(diff_blue as u8),
+        previous.alpha(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_index;
+    use crate::{
+        decode, encode, encode_with_options,
+        header::ColorSpace,
+        pixel::{Pixel, SupportedChannels},
+        EncodeOptions,
+    };
+
+    /// A noisy gradient, generated with a simple linear congruential generator
+    fn noisy_gradient(width: usize, height: usize) -> Vec<Pixel<4>> {
+        let mut state = 0x2545_f491u32;
+        let mut noise = || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8 % 5
+        };
+
+        (0..width * height)
+            .map(|i| {
+                let (x, y) = ((i % width) as u8, (i / width) as u8);
+                Pixel::rgba(
+                    x.wrapping_add(noise()),
+                    y.wrapping_add(noise()),
+                    x.wrapping_add(y).wrapping_add(noise()),
+                    250u8.wrapping_add(noise()),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn skips_unwritten_index_slots() {
+        let mut seen_pixels = [Pixel::<4>::default(); 64];
+        seen_pixels[0] = Pixel::rgba(64, 0, 0, 0);
+        let pixel = Pixel::rgba(1, 0, 0, 0);
+        let is_close = |candidate: Pixel<4>| candidate.red() <= 2 && candidate.alpha() == 0;
+
+        // NB: Every slot but `0` still holds its default `(0, 0, 0, 0)`, which hashes to `0`
+        assert_eq!(find_index(&seen_pixels, pixel, is_close), None);
+
+        seen_pixels[0] = Pixel::rgba(0, 0, 0, 0);
+        assert_eq!(find_index(&seen_pixels, pixel, is_close), Some(0));
+    }
+
+    fn encode_lossy(pixels: &[Pixel<4>], width: u32, height: u32, max_error: u8) -> Vec<u8> {
+        let options = EncodeOptions {
+            max_error,
+            ..Default::default()
+        };
+
+        let mut buf = vec![];
+        encode_with_options(&mut buf, pixels, width, height, ColorSpace::Srgb, &options).unwrap();
+
+        buf
+    }
+
+    #[test]
+    fn error_is_bounded() {
+        let pixels = noisy_gradient(200, 100);
+
+        for max_error in [1, 2, 4, 16] {
+            let buf = encode_lossy(&pixels, 200, 100, max_error);
+            let (_, decoded) = decode::<4>(&mut buf.as_slice()).unwrap();
+
+            for (pixel, decoded) in pixels.iter().zip(&decoded) {
+                for (a, b) in pixel.as_inner_rgba().iter().zip(decoded.as_inner_rgba()) {
+                    assert!(a.abs_diff(b) <= max_error, "{pixel:?} {decoded:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn output_is_smaller_than_lossless() {
+        let pixels = noisy_gradient(200, 100);
+
+        let mut lossless = vec![];
+        encode(&mut lossless, &pixels, 200, 100, ColorSpace::Srgb).unwrap();
+
+        let mut previous_size = lossless.len();
+        for max_error in [1, 2, 4, 16] {
+            let size = encode_lossy(&pixels, 200, 100, max_error).len();
+            assert!(
+                size < previous_size,
+                "{max_error}: {size} >= {previous_size}"
+            );
+            previous_size = size;
+        }
+    }
+
+    #[test]
+    fn noisy_flat_region_becomes_runs() {
+        let pixels: Vec<_> = (0..620u32)
+            .map(|i| {
+                let v = 100 + (i * 7 % 3) as u8;
+                Pixel::rgba(v, v, v, 255)
+            })
+            .collect();
+
+        let buf = encode_lossy(&pixels, 62, 10, 2);
+
+        // NB: Header, end marker, a single colour and `10` full runs
+        assert_eq!(buf.len(), 14 + 8 + 4 + 10);
+
+        let (_, decoded) = decode::<4>(&mut buf.as_slice()).unwrap();
+        assert!(decoded
+            .iter()
+            .all(|&pixel| pixel == Pixel::rgba(100, 100, 100, 255)));
+    }
+}
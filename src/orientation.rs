@@ -0,0 +1,336 @@
+//! Cropping, flipping and rotating images while they are decoded
+//!
+//! [`DecodeOptions`](crate::DecodeOptions) can crop, flip and rotate the returned pixels, which
+//! are applied in this order. Pixels outside of the [`Crop`] are dropped as they are decoded. When
+//! rows keep their order, as for a row-major image that is only cropped or flipped horizontally,
+//! the output grows with decoded pixels and flipped rows are reversed afterwards. Otherwise the
+//! whole image is decoded first, then each pixel is written to its place in the output.
+
+use crate::{Error, Result};
+
+/// Rectangle of the image to keep, in the coordinates of the stored image
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Clockwise rotation by a multiple of 90°
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Rotation {
+    #[default]
+    None,
+    Clockwise90,
+    Clockwise180,
+    Clockwise270,
+}
+
+/// Where each pixel of the stored image lands in the output
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Placement {
+    crop: Crop,
+    flip_horizontal: bool,
+    flip_vertical: bool,
+    rotation: Rotation,
+}
+
+impl Placement {
+    /// Places pixels of an image of `width` and `height` by cropping to `crop` (the whole image if
+    /// `None`), flipping, then rotating.
+    ///
+    /// Returns `Err` if the `crop` is not entirely within the image ([`Error::InvalidRegion`]).
+    pub(crate) fn new(
+        width: u32,
+        height: u32,
+        crop: Option<Crop>,
+        flip_horizontal: bool,
+        flip_vertical: bool,
+        rotation: Rotation,
+    ) -> Result<Self> {
+        let crop = crop.unwrap_or(Crop {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        });
+
+        let within = |start: u32, length: u32, size: u32| {
+            start.checked_add(length).is_some_and(|end| end <= size)
+        };
+        if !within(crop.x, crop.width, width) || !within(crop.y, crop.height, height) {
+            return Err(Error::InvalidRegion {
+                x: crop.x,
+                y: crop.y,
+                width: crop.width,
+                height: crop.height,
+            });
+        }
+
+        Ok(Self {
+            crop,
+            flip_horizontal,
+            flip_vertical,
+            rotation,
+        })
+    }
+
+    /// Whether every pixel stays where it is
+    pub(crate) fn is_identity(&self, width: u32, height: u32) -> bool {
+        self.crop.width == width
+            && self.crop.height == height
+            && !self.flip_horizontal
+            && !self.flip_vertical
+            && self.rotation == Rotation::None
+    }
+
+    /// Whether pixels of each row stay in the same row, and rows stay in the same order
+    pub(crate) fn keeps_rows(&self) -> bool {
+        !self.flip_vertical && self.rotation == Rotation::None
+    }
+
+    /// Width and height of the output
+    pub(crate) fn size(&self) -> (u32, u32) {
+        match self.rotation {
+            Rotation::None | Rotation::Clockwise180 => (self.crop.width, self.crop.height),
+            Rotation::Clockwise90 | Rotation::Clockwise270 => (self.crop.height, self.crop.width),
+        }
+    }
+
+    /// Index in the row-major output of the pixel at (`x`, `y`) in the stored image, or `None` if
+    /// it is cropped out
+    pub(crate) fn index(&self, x: u32, y: u32) -> Option<usize> {
+        let Crop {
+            width: crop_width,
+            height: crop_height,
+            ..
+        } = self.crop;
+
+        let x = x.checked_sub(self.crop.x).filter(|&x| x < crop_width)?;
+        let y = y.checked_sub(self.crop.y).filter(|&y| y < crop_height)?;
+
+        let x = match self.flip_horizontal {
+            true => crop_width - 1 - x,
+            false => x,
+        };
+        let y = match self.flip_vertical {
+            true => crop_height - 1 - y,
+            false => y,
+        };
+
+        let (x, y) = match self.rotation {
+            Rotation::None => (x, y),
+            Rotation::Clockwise90 => (crop_height - 1 - y, x),
+            Rotation::Clockwise180 => (crop_width - 1 - x, crop_height - 1 - y),
+            Rotation::Clockwise270 => (y, crop_width - 1 - x),
+        };
+
+        let (width, _) = self.size();
+        Some(y as usize * width as usize + x as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        decode_with_options, encode, encode_with_options, pixel::SupportedChannels, ColorSpace,
+        DecodeOptions, EncodeOptions, Pixel, Profile, Traversal,
+    };
+    use Rotation::{Clockwise180 as R180, Clockwise270 as R270, Clockwise90 as R90};
+
+    /// A 3x2 image with every pixel distinct
+    ///
+    /// ```text
+    /// 0 1 2
+    /// 3 4 5
+    /// ```
+    fn image() -> Vec<u8> {
+        let pixels: Vec<_> = (0..6).map(|i| Pixel::rgb(i * 40, 0, 0)).collect();
+
+        let mut buf = vec![];
+        encode(&mut buf, &pixels, 3, 2, ColorSpace::Srgb).unwrap();
+        buf
+    }
+
+    fn decode_red(options: &DecodeOptions) -> Result<(u32, u32, Vec<u8>)> {
+        let (header, pixels) = decode_with_options::<3>(&mut image().as_slice(), options)?;
+        let red = pixels
+            .iter()
+            .map(|pixel| pixel.as_inner_rgb()[0] / 40)
+            .collect();
+
+        Ok((header.width(), header.height(), red))
+    }
+
+    #[test]
+    fn flips_and_rotates() {
+        let cases = [
+            (false, false, Rotation::None, (3, 2), [0, 1, 2, 3, 4, 5]),
+            (true, false, Rotation::None, (3, 2), [2, 1, 0, 5, 4, 3]),
+            (false, true, Rotation::None, (3, 2), [3, 4, 5, 0, 1, 2]),
+            (false, false, R90, (2, 3), [3, 0, 4, 1, 5, 2]),
+            (false, false, R180, (3, 2), [5, 4, 3, 2, 1, 0]),
+            (false, false, R270, (2, 3), [2, 5, 1, 4, 0, 3]),
+            (true, true, R180, (3, 2), [0, 1, 2, 3, 4, 5]),
+        ];
+
+        for (flip_horizontal, flip_vertical, rotation, (width, height), expected) in cases {
+            let options = DecodeOptions {
+                flip_horizontal,
+                flip_vertical,
+                rotation,
+                ..Default::default()
+            };
+
+            assert_eq!(
+                decode_red(&options).unwrap(),
+                (width, height, expected.to_vec()),
+                "{options:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn crops_before_flipping() {
+        let options = DecodeOptions {
+            crop: Some(Crop {
+                x: 1,
+                y: 0,
+                width: 2,
+                height: 2,
+            }),
+            flip_vertical: true,
+            rotation: Rotation::Clockwise90,
+            ..Default::default()
+        };
+
+        // NB: Cropped to `1 2 / 4 5`, flipped to `4 5 / 1 2`, then rotated
+        assert_eq!(decode_red(&options).unwrap(), (2, 2, vec![1, 4, 2, 5]));
+    }
+
+    #[test]
+    fn places_pixels_of_any_traversal() {
+        let (width, height) = (7, 5);
+        let pixels: Vec<_> = (0..width * height)
+            .map(|i| Pixel::rgb(i as u8, 0, 0))
+            .collect();
+        let options = DecodeOptions {
+            crop: Some(Crop {
+                x: 1,
+                y: 1,
+                width: 5,
+                height: 3,
+            }),
+            rotation: R90,
+            ..Default::default()
+        };
+
+        let mut expected = None;
+        for traversal in [Traversal::RowMajor, Traversal::Hilbert] {
+            let encode_options = EncodeOptions {
+                profile: Profile::Extended,
+                traversal,
+                ..Default::default()
+            };
+            let mut buf = vec![];
+            encode_with_options(
+                &mut buf,
+                &pixels,
+                width,
+                height,
+                ColorSpace::Srgb,
+                &encode_options,
+            )
+            .unwrap();
+
+            let decoded = decode_with_options::<3>(&mut buf.as_slice(), &options).unwrap();
+            assert_eq!((decoded.0.width(), decoded.0.height()), (3, 5));
+            assert_eq!(*expected.get_or_insert(decoded.1.clone()), decoded.1);
+        }
+
+        // NB: The top right pixel of the crop ends up at the bottom right
+        assert_eq!(expected.unwrap()[14], Pixel::rgb(12, 0, 0));
+    }
+
+    #[test]
+    fn rejects_crop_outside_image() {
+        let options = DecodeOptions {
+            crop: Some(Crop {
+                x: 2,
+                y: 1,
+                width: 2,
+                height: 1,
+            }),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            decode_red(&options),
+            Err(Error::InvalidRegion { x: 2, .. })
+        ));
+    }
+
+    /// Header of an image claiming the largest size, without any data
+    fn huge_header() -> Vec<u8> {
+        let mut buf = b"qoif".to_vec();
+        buf.extend(u32::MAX.to_be_bytes());
+        buf.extend(u32::MAX.to_be_bytes());
+        buf.extend([4, 0]);
+        buf
+    }
+
+    #[test]
+    fn decodes_pixels_before_placing_them_out_of_order() {
+        let flipped = DecodeOptions {
+            flip_vertical: true,
+            ..Default::default()
+        };
+        let rotated = DecodeOptions {
+            rotation: R90,
+            ..Default::default()
+        };
+
+        // NB: The output is only allocated once every pixel is decoded, so the missing data is
+        // NB: noticed first
+        for options in [flipped, rotated] {
+            assert!(matches!(
+                decode_with_options::<4>(&mut huge_header().as_slice(), &options),
+                Err(Error::IoError(error)) if error.kind() == std::io::ErrorKind::UnexpectedEof
+            ));
+        }
+    }
+
+    #[test]
+    fn crops_and_mirrors_rows_as_they_are_decoded() {
+        let options = DecodeOptions {
+            crop: Some(Crop {
+                x: 0,
+                y: 0,
+                width: u32::MAX,
+                height: 1,
+            }),
+            flip_horizontal: true,
+            ..Default::default()
+        };
+
+        // NB: The output grows with decoded pixels, so the missing data is noticed first
+        assert!(matches!(
+            decode_with_options::<4>(&mut huge_header().as_slice(), &options),
+            Err(Error::IoError(error)) if error.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+
+        let options = DecodeOptions {
+            crop: Some(Crop {
+                x: 1,
+                y: 0,
+                width: 2,
+                height: 2,
+            }),
+            flip_horizontal: true,
+            ..Default::default()
+        };
+        assert_eq!(decode_red(&options).unwrap(), (2, 2, vec![2, 1, 5, 4]));
+    }
+}
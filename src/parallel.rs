@@ -0,0 +1,245 @@
+//! Multi-threaded encoding of a single image
+
+use std::thread;
+
+use crate::{
+    constants::QOI_END_MARKER,
+    encode::{encode_ops, header},
+    header::ColorSpace,
+    io::Writer,
+    pixel::{Pixel, SupportedChannels},
+    run::run_length,
+    Error, Result,
+};
+
+/// Minimum number of pixels in a band, below which splitting costs more than it gains
+const MIN_BAND_SIZE: usize = 1 << 16;
+
+/// Same as [`encode`](crate::encode), but splits the image into horizontal bands encoded on up to
+/// `threads` threads. `0` uses [`std::thread::available_parallelism`].
+///
+/// Each band is encoded from the exact state a decoder has at its start, which only depends on
+/// the pixels above it:
+///
+/// - The previous pixel is simply the last pixel of the band above.
+/// - Every decoded pixel is stored into the running "hash set", so its slots hold the last pixel
+///   above with each hash. `QOI_OP_RUN`s only repeat the previous pixel, which is already stored,
+///   except for a run of the initial pixel at the very start of the image.
+///
+/// The bands are then joined into a single stream, which is read by any decoder following the
+/// specification. The output only differs from [`encode`](crate::encode) where a run of identical
+/// pixels crosses a band boundary, as it is split into two `QOI_OP_RUN`s.
+///
+/// # Errors
+/// See [`encode`](crate::encode).
+pub fn encode_parallel<const N: usize>(
+    writer: &mut impl Writer,
+    pixels: &[Pixel<N>],
+    width: u32,
+    height: u32,
+    color_space: ColorSpace,
+    threads: usize,
+) -> Result<usize>
+where
+    Pixel<N>: SupportedChannels,
+{
+    // Ensure size of image data provided is the same as the provided dimensions
+    let image_size = (width as usize).saturating_mul(height as usize);
+    if pixels.len() != image_size {
+        return Err(Error::UnmatchedDataSize {
+            data_size: pixels.len(),
+            header_size: image_size,
+        });
+    }
+
+    let threads = match threads {
+        0 => thread::available_parallelism().map_or(1, usize::from),
+        threads => threads,
+    };
+
+    // Split into bands of whole rows
+    let band_count = threads.min(image_size / MIN_BAND_SIZE).max(1);
+    let band_rows = (height as usize).div_ceil(band_count).max(1);
+
+    encode_bands(writer, pixels, width, height, color_space, band_rows)
+}
+
+/// Encodes `pixels` in bands of `band_rows` rows, each on its own thread
+fn encode_bands<const N: usize>(
+    writer: &mut impl Writer,
+    pixels: &[Pixel<N>],
+    width: u32,
+    height: u32,
+    color_space: ColorSpace,
+    band_rows: usize,
+) -> Result<usize>
+where
+    Pixel<N>: SupportedChannels,
+{
+    let bands: Vec<_> = pixels.chunks(band_rows * width.max(1) as usize).collect();
+
+    // Find the pixels each band leaves in the running "hash set"
+    // NB: A run of the initial pixel at the start of the image is never stored, and may span
+    // NB: several bands
+    let mut unstored = run_length(pixels, Pixel::new_initial());
+    let band_seen_pixels: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = bands
+            .iter()
+            .map(|band| {
+                let skip = unstored.min(band.len());
+                unstored -= skip;
+
+                scope.spawn(move || seen_pixels(&band[skip..]))
+            })
+            .collect();
+
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+
+    // Accumulate them into the state at the start of each band
+    let mut start_seen_pixels = Vec::with_capacity(bands.len());
+    let mut seen = [Pixel::<4>::default(); 64];
+    for band in &band_seen_pixels {
+        start_seen_pixels.push(seen);
+        for (slot, pixel) in seen.iter_mut().zip(band) {
+            if let Some(pixel) = pixel {
+                *slot = *pixel;
+            }
+        }
+    }
+
+    // Encode every band from its starting state
+    let encoded: Vec<Result<Vec<u8>>> = thread::scope(|scope| {
+        let handles: Vec<_> = bands
+            .iter()
+            .zip(start_seen_pixels)
+            .enumerate()
+            .map(|(i, (band, seen_pixels))| {
+                let previous_pixel = match i {
+                    0 => Pixel::new_initial(),
+                    _ => *bands[i - 1].last().unwrap(),
+                };
+
+                scope.spawn(move || {
+                    let mut buf = Vec::with_capacity(band.len());
+                    encode_ops(&mut buf, band, previous_pixel, seen_pixels)?;
+                    Ok(buf)
+                })
+            })
+            .collect();
+
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+
+    let mut written = 0;
+
+    written += writer.write_from_slice(&header::<N>(width, height, color_space).as_bytes())?;
+    for buf in encoded {
+        written += writer.write_from_slice(&buf?)?;
+    }
+    written += writer.write_from_slice(QOI_END_MARKER)?;
+
+    Ok(written)
+}
+
+/// Finds the last pixel of each hash in `pixels`, ie. the slots of the running "hash set" a
+/// decoder has written after decoding them
+fn seen_pixels<const N: usize>(pixels: &[Pixel<N>]) -> [Option<Pixel<4>>; 64]
+where
+    Pixel<N>: SupportedChannels,
+{
+    let mut seen = [None; 64];
+    for pixel in pixels {
+        seen[pixel.index_hash()] = Some(pixel.as_rgba());
+    }
+
+    seen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode, encode};
+
+    /// Noise with long runs and repeated colours crossing rows, so that bands start in the middle
+    /// of runs and can index pixels from the bands above
+    fn image(width: u32, height: u32) -> Vec<Pixel<4>> {
+        let mut state = 7u32;
+        (0..width * height)
+            .map(|i| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                match (i / 37) % 4 {
+                    0 => Pixel::rgba(0, 0, 0, 255),
+                    1 => Pixel::rgba(10, 20, 30, 255),
+                    2 => Pixel::rgba((state >> 16) as u8, (state >> 24) as u8, 3, 200),
+                    _ => Pixel::rgba((i % 5) as u8, (i % 7) as u8, 9, 255),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bands_round_trip() {
+        let (width, height) = (23, 40);
+        let pixels = image(width, height);
+
+        for band_rows in [1, 3, 7, 40, 100] {
+            let mut buf = vec![];
+            encode_bands(
+                &mut buf,
+                &pixels,
+                width,
+                height,
+                ColorSpace::Srgb,
+                band_rows,
+            )
+            .unwrap();
+
+            let (header, decoded) = decode::<4>(&mut buf.as_slice()).unwrap();
+            assert_eq!((header.width(), header.height()), (width, height));
+            assert_eq!(decoded, pixels, "band_rows: {band_rows}");
+        }
+    }
+
+    #[test]
+    fn single_band_matches_encode() {
+        let (width, height) = (23, 40);
+        let pixels = image(width, height);
+
+        let mut expected = vec![];
+        encode(&mut expected, &pixels, width, height, ColorSpace::Srgb).unwrap();
+
+        let mut buf = vec![];
+        let written = encode_parallel(&mut buf, &pixels, width, height, ColorSpace::Srgb, 4);
+
+        assert_eq!(written.unwrap(), buf.len());
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn bands_start_after_initial_run() {
+        // NB: The leading run of the initial pixel is never stored, so the second band has to
+        // NB: emit it again rather than index it
+        let mut pixels = vec![Pixel::rgb(0, 0, 0); 10];
+        pixels.extend([Pixel::rgb(5, 5, 5), Pixel::rgb(0, 0, 0)]);
+
+        let mut buf = vec![];
+        encode_bands(&mut buf, &pixels, 1, 12, ColorSpace::Srgb, 11).unwrap();
+
+        let (_, decoded) = decode::<3>(&mut buf.as_slice()).unwrap();
+        assert_eq!(decoded, pixels);
+    }
+
+    #[test]
+    fn rejects_unmatched_size() {
+        let pixels = image(4, 4);
+
+        assert!(matches!(
+            encode_parallel(&mut vec![], &pixels, 4, 5, ColorSpace::Srgb, 2),
+            Err(Error::UnmatchedDataSize {
+                data_size: 16,
+                header_size: 20,
+            })
+        ));
+    }
+}
@@ -1,4 +1,6 @@
+// NB: Transparent so that a slice of pixels can be scanned as bytes, see `run`
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(transparent)]
 pub struct Pixel<const N: usize>([u8; N]);
 
 impl Pixel<3> {
@@ -22,6 +24,8 @@ impl From<Pixel<3>> for Pixel<4> {
 pub trait SupportedChannels {
     fn new_initial() -> Self;
 
+    fn from_inner_rgba(rgba: [u8; 4]) -> Self;
+
     fn red(&self) -> u8;
     fn green(&self) -> u8;
     fn blue(&self) -> u8;
@@ -38,6 +42,10 @@ impl SupportedChannels for Pixel<3> {
         Self([0, 0, 0])
     }
 
+    fn from_inner_rgba(rgba: [u8; 4]) -> Self {
+        Self([rgba[0], rgba[1], rgba[2]])
+    }
+
     fn red(&self) -> u8 {
         self.0[0]
     }
@@ -72,6 +80,10 @@ impl SupportedChannels for Pixel<4> {
         Self([0, 0, 0, 255])
     }
 
+    fn from_inner_rgba(rgba: [u8; 4]) -> Self {
+        Self(rgba)
+    }
+
     fn red(&self) -> u8 {
         self.0[0]
     }
@@ -112,6 +124,15 @@ where
             + self.alpha() as usize * 11)
             % 64
     }
+
+    /// Index of the pixel in the 256 pixel "hash set" of the extended profile
+    pub(crate) fn long_index_hash(&self) -> usize {
+        (self.red() as usize * 3
+            + self.green() as usize * 5
+            + self.blue() as usize * 7
+            + self.alpha() as usize * 11)
+            % 256
+    }
 }
 
 impl<const N: usize> Default for Pixel<N>
@@ -0,0 +1,200 @@
+//! Codec profiles selecting the set of `QOI_OP`s
+//!
+//! The [`Profile::Standard`] profile is the format of the specification, with its six `QOI_OP`s.
+//! The [`Profile::Extended`] profile is an experimental variant for trying out format tweaks, which
+//! only [`decode`](crate::decode) and friends understand. Its images start with the magic bytes
+//! `b"qoie"` instead of `b"qoif"`, but are otherwise laid out the same.
+//!
+//! # Extended `QOI_OP`s
+//! The extended profile shortens `QOI_OP_RUN` to runs of `1..=59`, freeing its last three tags:
+//!
+//! | Tag    | Op                    | Following bytes | Pixel                                       |
+//! |--------|-----------------------|-----------------|---------------------------------------------|
+//! | `0xfb` | `QOI_OP_ALPHA`        | 1               | Previous pixel with the given alpha         |
+//! | `0xfc` | `QOI_OP_RUN_LONG`     | 2               | Previous pixel repeated `1..=65536` times   |
+//! | `0xfd` | `QOI_OP_INDEX_LONG`   | 1               | Entry of a second, 256 pixel "hash set"     |
+//!
+//! The run length of `QOI_OP_RUN_LONG` is stored in big-endian with a bias of `-1`. The second
+//! "hash set" is indexed by `(r * 3 + g * 5 + b * 7 + a * 11) % 256` and updated along with the
+//! first one on every pixel that is not part of a run.
+//!
+//! The upper four bits of the channels and color space bytes of an extended header hold the
+//! [`Transform`](crate::transform::Transform) and [`Traversal`](crate::traversal::Traversal) the
+//! pixels were encoded with.
+
+use crate::{
+    constants::{QOI_EXTENDED_MAGIC, QOI_MAGIC},
+    header::Header,
+    io::Reader,
+    transform::Transform,
+    traversal::Traversal,
+    Error, Result,
+};
+
+/// Set of `QOI_OP`s used to encode an image
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Profile {
+    /// The `QOI_OP`s of the specification
+    #[default]
+    Standard,
+
+    /// The standard `QOI_OP`s with shorter one byte runs, plus long runs, a larger index and
+    /// alpha-only changes
+    Extended,
+}
+
+impl Profile {
+    /// Magic bytes of images encoded with this profile
+    pub(crate) fn magic(self) -> &'static [u8; 4] {
+        match self {
+            Profile::Standard => QOI_MAGIC,
+            Profile::Extended => QOI_EXTENDED_MAGIC,
+        }
+    }
+
+    /// Encoded `header` of an image with this profile, recording its `extensions`. Standard
+    /// images cannot record any.
+    pub(crate) fn header_bytes(
+        self,
+        header: &Header,
+        extensions: Extensions,
+    ) -> [u8; Header::SIZE] {
+        let mut bytes = header.as_bytes();
+        bytes[0..4].copy_from_slice(self.magic());
+
+        if self == Profile::Extended {
+            bytes[12] |= extensions.transform.to_u8() << 4;
+            bytes[13] |= extensions.traversal.to_u8() << 4;
+        }
+
+        bytes
+    }
+}
+
+/// How the pixels of an extended image are laid out before being encoded into `QOI_OP`s
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Extensions {
+    pub(crate) traversal: Traversal,
+    pub(crate) transform: Transform,
+}
+
+/// Reads the header of an image encoded with any profile from the `reader`, returning it along
+/// with the profile its magic bytes stand for and the extensions it records.
+pub(crate) fn read_header(reader: &mut impl Reader) -> Result<(Header, Profile, Extensions)> {
+    let mut bytes = [0; Header::SIZE];
+    reader.read_to_slice(&mut bytes)?;
+
+    let profile = match &bytes[0..4] {
+        magic if magic == QOI_MAGIC => Profile::Standard,
+        magic if magic == QOI_EXTENDED_MAGIC => Profile::Extended,
+        magic => return Err(Error::InvalidMagic(magic.try_into().unwrap())),
+    };
+
+    // NB: Past the magic bytes and the extensions, headers of every profile are the same
+    bytes[0..4].copy_from_slice(QOI_MAGIC);
+    let extensions = match profile {
+        Profile::Standard => Extensions::default(),
+        Profile::Extended => {
+            let transform =
+                Transform::from_u8(bytes[12] >> 4).ok_or(Error::InvalidTransform(bytes[12]))?;
+            let traversal =
+                Traversal::from_u8(bytes[13] >> 4).ok_or(Error::InvalidTraversal(bytes[13]))?;
+            bytes[12] &= 0x0f;
+            bytes[13] &= 0x0f;
+
+            Extensions {
+                traversal,
+                transform,
+            }
+        }
+    };
+
+    Ok((Header::from_bytes(bytes)?, profile, extensions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        constants::QOI_MAX_LONG_RUN, decode, decode_from_slice, encode, encode_with_options,
+        header::ColorSpace, pixel::Pixel, EncodeOptions,
+    };
+
+    fn encode_extended(pixels: &[Pixel<4>], width: u32, height: u32) -> Vec<u8> {
+        let options = EncodeOptions {
+            profile: Profile::Extended,
+            ..Default::default()
+        };
+
+        let mut buf = vec![];
+        encode_with_options(&mut buf, pixels, width, height, ColorSpace::Srgb, &options).unwrap();
+        buf
+    }
+
+    #[test]
+    fn extended_ops_are_smaller() {
+        // NB: Long runs, alpha-only changes and more distinct colours than the standard index holds
+        let mut pixels = vec![Pixel::rgba(10, 20, 30, 255); 2 * QOI_MAX_LONG_RUN + 1];
+        pixels.extend((0..=255).map(|a| Pixel::rgba(10, 20, 30, a)));
+        let palette: Vec<_> = (0..100u8)
+            .map(|i| Pixel::rgba(i.wrapping_mul(71), i.wrapping_mul(13), i ^ 0xa5, 255))
+            .collect();
+        for _ in 0..4 {
+            pixels.extend(&palette);
+        }
+        let (width, height) = (pixels.len() as u32, 1);
+
+        let mut standard = vec![];
+        encode(&mut standard, &pixels, width, height, ColorSpace::Srgb).unwrap();
+        let extended = encode_extended(&pixels, width, height);
+
+        assert_eq!(&standard[0..4], QOI_MAGIC);
+        assert_eq!(&extended[0..4], QOI_EXTENDED_MAGIC);
+        assert!(
+            extended.len() * 2 < standard.len(),
+            "{} >= {} / 2",
+            extended.len(),
+            standard.len()
+        );
+
+        assert_eq!(decode::<4>(&mut standard.as_slice()).unwrap().1, pixels);
+        assert_eq!(decode::<4>(&mut extended.as_slice()).unwrap().1, pixels);
+    }
+
+    #[test]
+    fn long_runs_round_trip_at_their_limit() {
+        for length in [QOI_MAX_LONG_RUN - 1, QOI_MAX_LONG_RUN, QOI_MAX_LONG_RUN + 1] {
+            let mut pixels = vec![Pixel::rgba(1, 2, 3, 4)];
+            pixels.extend(std::iter::repeat_n(Pixel::rgba(5, 6, 7, 8), length));
+            let buf = encode_extended(&pixels, pixels.len() as u32, 1);
+
+            assert_eq!(decode::<4>(&mut buf.as_slice()).unwrap().1, pixels);
+        }
+    }
+
+    #[test]
+    fn standard_decoders_reject_extended_images() {
+        let pixels = [Pixel::rgba(1, 2, 3, 4); 4];
+        let buf = encode_extended(&pixels, 2, 2);
+
+        assert!(matches!(
+            decode_from_slice::<4>(&buf),
+            Err(Error::InvalidMagic(magic)) if &magic == QOI_EXTENDED_MAGIC
+        ));
+    }
+
+    #[test]
+    fn near_lossless_keeps_standard_profile() {
+        let pixels = [Pixel::rgba(1, 2, 3, 4); 4];
+        let options = EncodeOptions {
+            profile: Profile::Extended,
+            max_error: 2,
+            ..Default::default()
+        };
+
+        let mut buf = vec![];
+        encode_with_options(&mut buf, &pixels, 2, 2, ColorSpace::Srgb, &options).unwrap();
+
+        assert_eq!(&buf[0..4], QOI_MAGIC);
+    }
+}
@@ -0,0 +1,426 @@
+//! Palette quantization for images that can afford to lose colours, such as UI and pixel art
+//!
+//! [`quantize`] reduces an image to at most 64 colours with median cut, then moves each colour of
+//! the palette to the closest one whose `QOI_OP_INDEX` hash no other colour of the palette has. As
+//! only palette colours appear in the output, every one of them stays in the running "hash set"
+//! once seen, so every pixel that is neither new nor part of a run becomes a single byte
+//! `QOI_OP_INDEX`. The output is plain pixels, which [`encode`](crate::encode) stores as standard
+//! QOI.
+//!
+//! Palette colours are picked over every channel of [`Pixel<N>`], so alpha is included for
+//! `N == 4`. The optional Floyd-Steinberg dithering only spreads the error of the colour channels,
+//! as dithered alpha shows as noise along the edges of transparent areas.
+
+use std::collections::HashMap;
+
+use crate::{
+    pixel::{Pixel, SupportedChannels},
+    Error, Result,
+};
+
+/// Number of colours the `QOI_OP_INDEX` "hash set" holds
+const MAX_COLORS: usize = 64;
+
+/// Largest change of a colour channel tried while moving a palette colour to a free hash
+///
+/// NB: Changing each of red, green and blue by up to 8 in a single direction already reaches every
+/// NB: hash, so a free one is always found even at the limits of a channel.
+const MAX_NUDGE: i32 = 8;
+
+/// Options controlling how [`quantize`] reduces an image
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuantizeOptions {
+    /// Maximum number of colours in the palette, where values above `64` are treated as `64` and
+    /// `0` as `1`
+    pub max_colors: u8,
+
+    /// Whether to spread the error of each pixel to its neighbours with Floyd-Steinberg dithering,
+    /// which keeps gradients smooth at the cost of fewer runs
+    pub dither: bool,
+}
+
+impl Default for QuantizeOptions {
+    fn default() -> Self {
+        Self {
+            max_colors: MAX_COLORS as u8,
+            dither: false,
+        }
+    }
+}
+
+/// An image reduced to a palette by [`quantize`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Quantized<const N: usize> {
+    /// Colours of the palette, each with a distinct `QOI_OP_INDEX` hash, most frequent first
+    pub palette: Vec<Pixel<N>>,
+
+    /// Pixels of the image, each one of the `palette`
+    pub pixels: Vec<Pixel<N>>,
+}
+
+/// Reduces `pixels` of `width` and `height` to a palette of colours with distinct `QOI_OP_INDEX`
+/// hashes. An image with no more colours than allowed keeps them, apart from those moved to a free
+/// hash.
+///
+/// # Errors
+/// This function returns `Err` if the provided `width` and `height` differs from the length of
+/// `pixels` ([`Error::UnmatchedDataSize`]).
+pub fn quantize<const N: usize>(
+    pixels: &[Pixel<N>],
+    width: u32,
+    height: u32,
+    options: &QuantizeOptions,
+) -> Result<Quantized<N>>
+where
+    Pixel<N>: SupportedChannels,
+{
+    let image_size = (width as usize).saturating_mul(height as usize);
+    if pixels.len() != image_size {
+        return Err(Error::UnmatchedDataSize {
+            data_size: pixels.len(),
+            header_size: image_size,
+        });
+    }
+
+    let max_colors = (options.max_colors as usize).clamp(1, MAX_COLORS);
+    let palette = spread_hashes(median_cut(pixels, max_colors));
+
+    let pixels = match options.dither {
+        true => dither(pixels, width as usize, &palette),
+        false => {
+            // NB: Images reduced to a palette repeat colours a lot, so each match is only searched once
+            let mut nearest = HashMap::new();
+            pixels
+                .iter()
+                .map(|&pixel| {
+                    let rgba = pixel.as_inner_rgba();
+                    *nearest
+                        .entry(rgba)
+                        .or_insert_with(|| palette[nearest_color(&palette, rgba.map(i32::from))])
+                })
+                .collect()
+        }
+    };
+
+    Ok(Quantized { palette, pixels })
+}
+
+/// A box of colours of the histogram, split along its widest channel by median cut
+struct ColorBox {
+    /// Colours in the box along with their number of pixels
+    colors: Vec<([u8; 4], usize)>,
+}
+
+impl ColorBox {
+    fn population(&self) -> usize {
+        self.colors.iter().map(|&(_, count)| count).sum()
+    }
+
+    /// Widest range of any channel, along with that channel
+    fn widest_channel(&self) -> (u8, usize) {
+        (0..4)
+            .map(|channel| {
+                let values = self.colors.iter().map(|(rgba, _)| rgba[channel]);
+                let range = values.clone().max().unwrap() - values.min().unwrap();
+                (range, channel)
+            })
+            .max()
+            .unwrap()
+    }
+
+    /// Mean colour of the box, weighted by the number of pixels of each colour
+    fn mean(&self) -> [u8; 4] {
+        let population = self.population();
+
+        std::array::from_fn(|channel| {
+            let sum: usize = self
+                .colors
+                .iter()
+                .map(|&(rgba, count)| rgba[channel] as usize * count)
+                .sum();
+            ((sum + population / 2) / population) as u8
+        })
+    }
+
+    /// Splits the box in two at the median pixel along its widest channel
+    fn split(mut self) -> (ColorBox, ColorBox) {
+        let (_, channel) = self.widest_channel();
+        self.colors.sort_unstable_by_key(|(rgba, _)| rgba[channel]);
+
+        // NB: Both halves keep at least one colour, even if the median pixel is the first or last
+        let half = self.population() / 2;
+        let mut seen = 0;
+        let median = self
+            .colors
+            .iter()
+            .position(|&(_, count)| {
+                seen += count;
+                seen > half
+            })
+            .unwrap()
+            .clamp(1, self.colors.len() - 1);
+
+        let upper = self.colors.split_off(median);
+        (self, ColorBox { colors: upper })
+    }
+}
+
+/// Picks up to `max_colors` colours for `pixels` with median cut, sorted by number of pixels
+fn median_cut<const N: usize>(pixels: &[Pixel<N>], max_colors: usize) -> Vec<([u8; 4], usize)>
+where
+    Pixel<N>: SupportedChannels,
+{
+    let mut histogram = HashMap::new();
+    for pixel in pixels {
+        *histogram.entry(pixel.as_inner_rgba()).or_insert(0) += 1;
+    }
+
+    let mut boxes = vec![ColorBox {
+        colors: histogram.into_iter().collect(),
+    }];
+
+    // NB: The box with the widest channel is split first, weighted by its population so that a
+    // NB: few stray pixels do not use up the palette
+    while boxes.len() < max_colors {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, color_box)| color_box.colors.len() > 1)
+            .max_by_key(|(_, color_box)| {
+                color_box.widest_channel().0 as usize * color_box.population()
+            });
+
+        let Some((i, _)) = widest else {
+            break;
+        };
+
+        let (lower, upper) = boxes.swap_remove(i).split();
+        boxes.push(lower);
+        boxes.push(upper);
+    }
+
+    let mut palette: Vec<_> = boxes
+        .iter()
+        .filter(|color_box| !color_box.colors.is_empty())
+        .map(|color_box| (color_box.mean(), color_box.population()))
+        .collect();
+    palette.sort_by_key(|&(rgba, count)| (std::cmp::Reverse(count), rgba));
+
+    palette
+}
+
+/// Moves each colour of `palette` to the closest colour with a `QOI_OP_INDEX` hash not taken by a
+/// more frequent one, merging colours that end up the same
+fn spread_hashes<const N: usize>(palette: Vec<([u8; 4], usize)>) -> Vec<Pixel<N>>
+where
+    Pixel<N>: SupportedChannels,
+{
+    let mut taken = [false; MAX_COLORS];
+    let mut spread: Vec<Pixel<N>> = vec![];
+    let nudges = nudges();
+
+    for (rgba, _) in palette {
+        let pixel = Pixel::<N>::from_inner_rgba(rgba);
+
+        // NB: Two boxes may have the same mean, or be moved onto the same colour
+        if spread.contains(&pixel) {
+            continue;
+        }
+
+        let nudged = nudges
+            .iter()
+            .filter_map(|&[dr, dg, db]| {
+                let channel = |value: u8, delta: i32| u8::try_from(value as i32 + delta).ok();
+                let [r, g, b, a] = rgba;
+
+                Some(Pixel::<N>::from_inner_rgba([
+                    channel(r, dr)?,
+                    channel(g, dg)?,
+                    channel(b, db)?,
+                    a,
+                ]))
+            })
+            .find(|pixel| !taken[pixel.index_hash()] && !spread.contains(pixel))
+            .expect("every hash is reachable within `MAX_NUDGE`");
+
+        taken[nudged.index_hash()] = true;
+        spread.push(nudged);
+    }
+
+    spread
+}
+
+/// Changes of red, green and blue ordered by increasing squared distance, starting with no change
+fn nudges() -> Vec<[i32; 3]> {
+    let range = -MAX_NUDGE..=MAX_NUDGE;
+    let mut nudges: Vec<_> = range
+        .clone()
+        .flat_map(|dr| {
+            let range = range.clone();
+            range
+                .clone()
+                .flat_map(move |dg| range.clone().map(move |db| [dr, dg, db]))
+        })
+        .collect();
+    nudges.sort_by_key(|delta| delta.iter().map(|d| d * d).sum::<i32>());
+
+    nudges
+}
+
+/// Index of the colour of `palette` closest to `rgba`, by squared distance over every channel
+fn nearest_color<const N: usize>(palette: &[Pixel<N>], rgba: [i32; 4]) -> usize
+where
+    Pixel<N>: SupportedChannels,
+{
+    (0..palette.len())
+        .min_by_key(|&i| {
+            palette[i]
+                .as_inner_rgba()
+                .iter()
+                .zip(rgba)
+                .map(|(&c, v)| (c as i32 - v).pow(2))
+                .sum::<i32>()
+        })
+        .unwrap()
+}
+
+/// Maps `pixels` of `width` to `palette` with Floyd-Steinberg dithering of the colour channels
+fn dither<const N: usize>(pixels: &[Pixel<N>], width: usize, palette: &[Pixel<N>]) -> Vec<Pixel<N>>
+where
+    Pixel<N>: SupportedChannels,
+{
+    // NB: Errors of the current and next row, with a pixel of padding on either side
+    let mut errors = [vec![[0; 3]; width + 2], vec![[0; 3]; width + 2]];
+    let mut output = Vec::with_capacity(pixels.len());
+
+    for row in pixels.chunks(width.max(1)) {
+        for (x, pixel) in row.iter().enumerate() {
+            let [r, g, b, a] = pixel.as_inner_rgba().map(i32::from);
+            let error = errors[0][x + 1];
+            let rgba = [
+                (r + error[0] / 16).clamp(0, 255),
+                (g + error[1] / 16).clamp(0, 255),
+                (b + error[2] / 16).clamp(0, 255),
+                a,
+            ];
+
+            let color = palette[nearest_color(palette, rgba)];
+            let mapped = color.as_inner_rgba();
+            output.push(color);
+
+            for channel in 0..3 {
+                let error = rgba[channel] - mapped[channel] as i32;
+                errors[0][x + 2][channel] += error * 7;
+                errors[1][x][channel] += error * 3;
+                errors[1][x + 1][channel] += error * 5;
+                errors[1][x + 2][channel] += error;
+            }
+        }
+
+        errors.swap(0, 1);
+        errors[1].fill([0; 3]);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{encode, ColorSpace};
+
+    /// Colourful noise, with far more colours than a palette holds
+    fn noise(width: u32, height: u32) -> Vec<Pixel<4>> {
+        let mut state = 1u32;
+        (0..width * height)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let [r, g, b, a] = (state >> 8).to_le_bytes();
+                Pixel::rgba(r, g, b, a | 0x80)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn palette_has_distinct_hashes() {
+        let (width, height) = (64, 64);
+
+        for dither in [false, true] {
+            for max_colors in [1, 16, 64, 255] {
+                let options = QuantizeOptions { max_colors, dither };
+                let quantized = quantize(&noise(width, height), width, height, &options).unwrap();
+
+                let palette = &quantized.palette;
+                assert!(palette.len() <= (max_colors as usize).min(MAX_COLORS));
+
+                let mut hashes: Vec<_> = palette.iter().map(|pixel| pixel.index_hash()).collect();
+                hashes.sort_unstable();
+                hashes.dedup();
+                assert_eq!(hashes.len(), palette.len());
+
+                assert!(quantized.pixels.iter().all(|pixel| palette.contains(pixel)));
+            }
+        }
+    }
+
+    #[test]
+    fn repeats_become_single_bytes() {
+        let (width, height) = (64, 64);
+        let quantized = quantize(
+            &noise(width, height),
+            width,
+            height,
+            &QuantizeOptions::default(),
+        )
+        .unwrap();
+
+        let mut buf = vec![];
+        encode(&mut buf, &quantized.pixels, width, height, ColorSpace::Srgb).unwrap();
+
+        // NB: Each colour takes at most a 5 byte `QOI_OP_RGBA` when first seen, and a single byte
+        // NB: every other time
+        let limit = 14 + 8 + MAX_COLORS * 5 + (width * height) as usize;
+        assert!(buf.len() <= limit, "{} > {limit}", buf.len());
+    }
+
+    #[test]
+    fn keeps_few_colours_with_distinct_hashes() {
+        let colors = [
+            Pixel::rgb(0, 0, 0),
+            Pixel::rgb(255, 255, 255),
+            Pixel::rgb(255, 0, 0),
+        ];
+        let pixels: Vec<_> = (0..48).map(|i| colors[i * 7 % 3]).collect();
+
+        let quantized = quantize(&pixels, 8, 6, &QuantizeOptions::default()).unwrap();
+        assert_eq!(quantized.pixels, pixels);
+        assert_eq!(quantized.palette.len(), 3);
+    }
+
+    #[test]
+    fn moves_colliding_colours() {
+        // NB: Both hash to `0`
+        let colors = [Pixel::rgb(0, 0, 0), Pixel::rgb(64, 0, 0)];
+        let pixels: Vec<_> = (0..10).map(|i| colors[(i < 7) as usize]).collect();
+        assert_eq!(colors[0].index_hash(), colors[1].index_hash());
+
+        let quantized = quantize(&pixels, 10, 1, &QuantizeOptions::default()).unwrap();
+
+        // NB: The more frequent colour keeps its value, the other moves by as little as possible
+        assert_eq!(quantized.palette[0], colors[1]);
+        assert_ne!(quantized.palette[1], colors[0]);
+        assert_eq!(quantized.pixels[..7], [colors[1]; 7]);
+        assert!(quantized.palette[1]
+            .as_inner_rgb()
+            .iter()
+            .all(|&channel| channel <= 1));
+    }
+
+    #[test]
+    fn rejects_unmatched_size() {
+        assert!(matches!(
+            quantize(&noise(4, 4), 4, 5, &QuantizeOptions::default()),
+            Err(Error::UnmatchedDataSize { .. })
+        ));
+    }
+}
@@ -0,0 +1,448 @@
+//! Restart points for decoding rows from the middle of a QOI image
+//!
+//! A [`RestartIndex`] is built by scanning an image once, recording the full decoder state every
+//! `interval` rows. The index is stored separately from the image in a sidecar file, so the image
+//! itself is left untouched. Its layout is, with all integers in big-endian:
+//!
+//! | Field                          | Size in bytes      |
+//! |--------------------------------|--------------------|
+//! | Magic bytes `b"qoix"`          | 4                  |
+//! | Header of the image            | 14                 |
+//! | `interval`                     | 4                  |
+//! | Number of checkpoints          | 4                  |
+//! | Checkpoints                    | 276 per checkpoint |
+//!
+//! Each checkpoint holds, in order, its `offset` and `position` as `u64`s, then the RGBA channels
+//! of its `previous_pixel` followed by those of its 64 `seen_pixels`.
+
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::{
+    constants::QOI_END_MARKER,
+    decode::{DecoderState, MAX_PREALLOCATED_PIXELS},
+    header::Header,
+    io::{Counting, Reader, Writer},
+    pixel::{Pixel, SupportedChannels},
+    Error, Result,
+};
+
+/// Magic bytes of a restart index
+const RESTART_MAGIC: &[u8; 4] = b"qoix";
+
+/// Maximum number of checkpoints allocated up front, before any of them are read
+const MAX_PREALLOCATED_CHECKPOINTS: usize = 1 << 12;
+
+/// Full state of a decoder at the start of a `QOI_OP`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Offset of the `QOI_OP` in bytes, from the start of the image including its header
+    pub offset: u64,
+
+    /// Index of the first pixel decoded from the `QOI_OP`
+    pub position: u64,
+
+    /// Last pixel decoded before the `QOI_OP`
+    pub previous_pixel: Pixel<4>,
+
+    /// Running "hash set" of all seen pixels before the `QOI_OP`
+    pub seen_pixels: [Pixel<4>; 64],
+}
+
+impl Checkpoint {
+    fn state(&self) -> DecoderState {
+        DecoderState {
+            previous_pixel: self.previous_pixel,
+            seen_pixels: self.seen_pixels,
+            ..DecoderState::new()
+        }
+    }
+}
+
+/// Checkpoints into a QOI image, which allow decoding rows without decoding the image up to them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RestartIndex {
+    header: Header,
+    interval: u32,
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl RestartIndex {
+    /// Scans the QOI image read from the `reader`, recording a checkpoint at the first `QOI_OP`
+    /// starting at or after every `interval` rows. The first checkpoint is always at the first
+    /// `QOI_OP` of the image.
+    ///
+    /// # Errors
+    /// This function returns `Err` if `interval` is `0` ([`Error::InvalidInterval`]), and in the
+    /// same cases as [`decode`](crate::decode).
+    pub fn build(reader: &mut impl Reader, interval: u32) -> Result<Self> {
+        if interval == 0 {
+            return Err(Error::InvalidInterval(interval));
+        }
+
+        let mut reader = Counting::new(reader);
+
+        // Read header information
+        let header = {
+            let mut bytes = [0; Header::SIZE];
+            reader.read_to_slice(&mut bytes)?;
+            Header::from_bytes(bytes)?
+        };
+
+        let image_size = header.width() as u64 * header.height() as u64;
+        let checkpoint_size = (header.width() as u64 * interval as u64).max(1);
+
+        let mut checkpoints = vec![];
+        let mut state = DecoderState::new();
+        let mut position = 0;
+
+        // Decode each `QOI_OP`, checkpointing the state before it if it crossed into a new interval
+        while position < image_size {
+            if position >= checkpoints.len() as u64 * checkpoint_size {
+                checkpoints.push(Checkpoint {
+                    offset: reader.count(),
+                    position,
+                    previous_pixel: state.previous_pixel,
+                    seen_pixels: state.seen_pixels,
+                });
+            }
+
+            let (_, count) = state.decode_op(&mut reader)?;
+            position += count as u64;
+        }
+
+        // Check the end marker
+        {
+            let mut end_marker = [0; 8];
+            reader.read_to_slice(&mut end_marker)?;
+
+            if &end_marker != QOI_END_MARKER {
+                return Err(Error::InvalidEndMarker(end_marker));
+            }
+        }
+
+        Ok(Self {
+            header,
+            interval,
+            checkpoints,
+        })
+    }
+
+    /// Header of the indexed image
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Number of rows between checkpoints
+    pub fn interval(&self) -> u32 {
+        self.interval
+    }
+
+    /// Recorded checkpoints, in the order of their positions
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    /// Writes the index into the provided `writer`, returning the number of bytes written.
+    ///
+    /// # Errors
+    /// This function returns `Err` if either [`Writer::write_byte`] or
+    /// [`Writer::write_from_slice`] fails.
+    pub fn write(&self, writer: &mut impl Writer) -> Result<usize> {
+        let mut written = 0;
+
+        written += writer.write_from_slice(RESTART_MAGIC)?;
+        written += writer.write_from_slice(&self.header.as_bytes())?;
+        written += writer.write_from_slice(&self.interval.to_be_bytes())?;
+        written += writer.write_from_slice(&(self.checkpoints.len() as u32).to_be_bytes())?;
+
+        for checkpoint in &self.checkpoints {
+            written += writer.write_from_slice(&checkpoint.offset.to_be_bytes())?;
+            written += writer.write_from_slice(&checkpoint.position.to_be_bytes())?;
+
+            for pixel in std::iter::once(&checkpoint.previous_pixel).chain(&checkpoint.seen_pixels)
+            {
+                written += writer.write_from_slice(&pixel.as_inner_rgba())?;
+            }
+        }
+
+        Ok(written)
+    }
+
+    /// Reads an index previously written by [`RestartIndex::write`] from the `reader`.
+    ///
+    /// # Errors
+    /// This function returns `Err` if the `reader` fails, including when the data ends early, or
+    /// the magic bytes, stored header or interval are invalid (see [`Error::InvalidMagic`],
+    /// [`Error::InvalidChannelNumber`], [`Error::InvalidColorSpace`] and
+    /// [`Error::InvalidInterval`]).
+    pub fn read(reader: &mut impl Reader) -> Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_to_slice(&mut magic)?;
+        if &magic != RESTART_MAGIC {
+            return Err(Error::InvalidMagic(magic));
+        }
+
+        let header = {
+            let mut bytes = [0; Header::SIZE];
+            reader.read_to_slice(&mut bytes)?;
+            Header::from_bytes(bytes)?
+        };
+
+        let mut bytes = [0; 4];
+        reader.read_to_slice(&mut bytes)?;
+        let interval = u32::from_be_bytes(bytes);
+        if interval == 0 {
+            return Err(Error::InvalidInterval(interval));
+        }
+        reader.read_to_slice(&mut bytes)?;
+        let count = u32::from_be_bytes(bytes) as usize;
+
+        // NB: The count is not trusted with the allocation, as the data may end early
+        let mut checkpoints = Vec::with_capacity(count.min(MAX_PREALLOCATED_CHECKPOINTS));
+        for _ in 0..count {
+            let mut bytes = [0; 8];
+            reader.read_to_slice(&mut bytes)?;
+            let offset = u64::from_be_bytes(bytes);
+            reader.read_to_slice(&mut bytes)?;
+            let position = u64::from_be_bytes(bytes);
+
+            let mut read_pixel = || -> Result<Pixel<4>> {
+                let mut rgba = [0; 4];
+                reader.read_to_slice(&mut rgba)?;
+                Ok(Pixel::from_inner_rgba(rgba))
+            };
+
+            let previous_pixel = read_pixel()?;
+            let mut seen_pixels = [Pixel::default(); 64];
+            for pixel in &mut seen_pixels {
+                *pixel = read_pixel()?;
+            }
+
+            checkpoints.push(Checkpoint {
+                offset,
+                position,
+                previous_pixel,
+                seen_pixels,
+            });
+        }
+
+        Ok(Self {
+            header,
+            interval,
+            checkpoints,
+        })
+    }
+
+    /// Decodes `rows` rows starting at row `start` of the indexed image read from the `reader`,
+    /// resuming from the last checkpoint before them. Pixels are converted as by
+    /// [`decode`](crate::decode).
+    ///
+    /// The offsets of the checkpoints are from the start of the image, which must be at the start
+    /// of the `reader`. Decoding stops after the last requested row, so the rest of the image,
+    /// including its end marker, is never read.
+    ///
+    /// # Errors
+    /// This function returns `Err` in one of the following cases:
+    ///
+    /// 1. The rows are not entirely within the image ([`Error::InvalidRegion`])
+    /// 2. Either seeking or reading the `reader` fails, including when the data ends early
+    pub fn decode_rows<const N: usize, R: Read + Seek>(
+        &self,
+        reader: &mut R,
+        start: u32,
+        rows: u32,
+    ) -> Result<Vec<Pixel<N>>>
+    where
+        Pixel<N>: SupportedChannels,
+    {
+        if start
+            .checked_add(rows)
+            .is_none_or(|end| end > self.header.height())
+        {
+            return Err(Error::InvalidRegion {
+                x: 0,
+                y: start,
+                width: self.header.width(),
+                height: rows,
+            });
+        }
+
+        let width = self.header.width() as u64;
+        let (first, last) = (start as u64 * width, (start + rows) as u64 * width);
+
+        // NB: The rows are only allocated for as they are decoded, as the header is not trusted
+        let mut pixels = Vec::with_capacity(((last - first) as usize).min(MAX_PREALLOCATED_PIXELS));
+        if first == last {
+            return Ok(pixels);
+        }
+
+        // Resume from the last checkpoint before the rows, or the start of the image if there is
+        // none, such as in an index that was not built by `RestartIndex::build`
+        let checkpoint = self
+            .checkpoints
+            .iter()
+            .rev()
+            .find(|checkpoint| checkpoint.position <= first)
+            .copied()
+            .unwrap_or(Checkpoint {
+                offset: Header::SIZE as u64,
+                position: 0,
+                previous_pixel: Pixel::<4>::new_initial(),
+                seen_pixels: [Pixel::<4>::default(); 64],
+            });
+
+        reader
+            .seek(SeekFrom::Start(checkpoint.offset))
+            .map_err(Error::IoError)?;
+
+        let mut state = checkpoint.state();
+        let mut position = checkpoint.position;
+
+        while position < last {
+            let (pixel, count) = state.decode_op(reader)?;
+
+            // Only keep the pixels within the requested rows
+            let (start, end) = (position.max(first), (position + count as u64).min(last));
+            if start < end {
+                let pixel = Pixel::<N>::from_inner_rgba(pixel.as_inner_rgba());
+                pixels.extend(std::iter::repeat_n(pixel, (end - start) as usize));
+            }
+
+            position += count as u64;
+        }
+
+        Ok(pixels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{decode, encode, header::ColorSpace};
+
+    /// Rows of noise, runs crossing rows and repeated colours
+    fn image(width: u32, height: u32) -> Vec<Pixel<4>> {
+        let mut state = 3u32;
+        (0..width * height)
+            .map(|i| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                match (i / 50) % 3 {
+                    0 => Pixel::rgba(9, 9, 9, 255),
+                    1 => Pixel::rgba((state >> 16) as u8, (state >> 24) as u8, 7, 255),
+                    _ => Pixel::rgba((i % 3) as u8, 0, 0, (i % 2) as u8 * 255),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decodes_any_rows() {
+        let (width, height) = (17, 30);
+        let pixels = image(width, height);
+
+        let mut buf = vec![];
+        encode(&mut buf, &pixels, width, height, ColorSpace::Srgb).unwrap();
+
+        let index = RestartIndex::build(&mut buf.as_slice(), 4).unwrap();
+        assert_eq!(index.header().height(), height);
+        assert_eq!(index.checkpoints().len(), 8);
+        assert!(index
+            .checkpoints()
+            .iter()
+            .enumerate()
+            .all(|(i, checkpoint)| checkpoint.position >= (i as u32 * 4 * width) as u64));
+
+        let mut reader = Cursor::new(&buf);
+        for (start, rows) in [
+            (0, height),
+            (0, 1),
+            (3, 2),
+            (4, 4),
+            (13, 17),
+            (29, 1),
+            (5, 0),
+        ] {
+            let from = (start * width) as usize;
+            let to = ((start + rows) * width) as usize;
+
+            assert_eq!(
+                index.decode_rows::<4, _>(&mut reader, start, rows).unwrap(),
+                pixels[from..to],
+                "rows {start}..{}",
+                start + rows
+            );
+        }
+    }
+
+    #[test]
+    fn sidecar_round_trips() {
+        let (width, height) = (8, 20);
+        let pixels = image(width, height);
+
+        let mut buf = vec![];
+        encode(&mut buf, &pixels, width, height, ColorSpace::AllLinear).unwrap();
+        let index = RestartIndex::build(&mut buf.as_slice(), 3).unwrap();
+
+        let mut sidecar = vec![];
+        let written = index.write(&mut sidecar).unwrap();
+        assert_eq!(written, sidecar.len());
+        assert_eq!(sidecar.len(), 26 + 276 * index.checkpoints().len());
+
+        let read = RestartIndex::read(&mut sidecar.as_slice()).unwrap();
+        assert_eq!(read, index);
+
+        let (_, expected) = decode::<3>(&mut buf.as_slice()).unwrap();
+        assert_eq!(
+            read.decode_rows::<3, _>(&mut Cursor::new(&buf), 10, 10)
+                .unwrap(),
+            expected[80..]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_rows() {
+        let pixels = image(4, 4);
+
+        let mut buf = vec![];
+        encode(&mut buf, &pixels, 4, 4, ColorSpace::Srgb).unwrap();
+        let index = RestartIndex::build(&mut buf.as_slice(), 1).unwrap();
+
+        assert!(matches!(
+            index.decode_rows::<4, _>(&mut Cursor::new(&buf), 3, 2),
+            Err(Error::InvalidRegion {
+                y: 3,
+                height: 2,
+                ..
+            })
+        ));
+        assert!(matches!(
+            RestartIndex::read(&mut buf.as_slice()),
+            Err(Error::InvalidMagic(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_zero_interval() {
+        let mut buf = vec![];
+        encode(&mut buf, &image(4, 4), 4, 4, ColorSpace::Srgb).unwrap();
+
+        assert!(matches!(
+            RestartIndex::build(&mut buf.as_slice(), 0),
+            Err(Error::InvalidInterval(0))
+        ));
+
+        let mut sidecar = vec![];
+        RestartIndex::build(&mut buf.as_slice(), 1)
+            .unwrap()
+            .write(&mut sidecar)
+            .unwrap();
+        sidecar[18..22].fill(0);
+
+        assert!(matches!(
+            RestartIndex::read(&mut sidecar.as_slice()),
+            Err(Error::InvalidInterval(0))
+        ));
+    }
+}
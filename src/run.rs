@@ -0,0 +1,291 @@
+//! Fast detection of runs of identical pixels
+//!
+//! Pixels are compared a whole chunk at a time. On x86_64, with AVX2 if available and SSE2
+//! otherwise, and on aarch64 with NEON, each chunk is compared as raw bytes against the pixel
+//! repeated over the chunk, a few vector registers at a time. Other targets fold the comparisons of
+//! a chunk without branching on each of them, which lets the compiler vectorize the scan with
+//! whatever SIMD instructions the target supports.
+
+use crate::pixel::Pixel;
+
+/// Number of pixels compared at once
+const CHUNK_SIZE: usize = 32;
+
+/// Counts the number of leading `pixels` that are equal to `pixel`
+pub(crate) fn run_length<const N: usize>(pixels: &[Pixel<N>], pixel: Pixel<N>) -> usize {
+    let length = equal_chunks(pixels, pixel) * CHUNK_SIZE;
+    length + scalar_run_length(&pixels[length..], pixel)
+}
+
+/// Counts the number of leading chunks of `pixels` that are entirely equal to `pixel`
+fn equal_chunks<const N: usize>(pixels: &[Pixel<N>], pixel: Pixel<N>) -> usize {
+    // NB: Most runs end within the first chunk, which is not worth setting up a vector scan for
+    if pixels.len() < CHUNK_SIZE || pixels[CHUNK_SIZE - 1] != pixel {
+        return 0;
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        let pattern = [pixel; CHUNK_SIZE];
+        let (bytes, pattern) = (as_bytes(pixels), as_bytes(&pattern));
+
+        match std::arch::is_x86_feature_detected!("avx2") {
+            // SAFETY: AVX2 is available
+            true => unsafe { avx2::equal_chunks(bytes, pattern) },
+            // SAFETY: SSE2 is part of the x86_64 baseline
+            false => unsafe { sse2::equal_chunks(bytes, pattern) },
+        }
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        #[cfg(target_arch = "aarch64")]
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            let pattern = [pixel; CHUNK_SIZE];
+            // SAFETY: NEON is available
+            return unsafe { neon::equal_chunks(as_bytes(pixels), as_bytes(&pattern)) };
+        }
+
+        folded_equal_chunks(pixels, pixel)
+    }
+}
+
+#[cfg(any(test, not(target_arch = "x86_64")))]
+fn folded_equal_chunks<const N: usize>(pixels: &[Pixel<N>], pixel: Pixel<N>) -> usize {
+    pixels
+        .chunks_exact(CHUNK_SIZE)
+        // NB: Folding with `&` instead of short-circuiting keeps the loop branch-free
+        .take_while(|chunk| {
+            chunk
+                .iter()
+                .fold(true, |equal, other| equal & (*other == pixel))
+        })
+        .count()
+}
+
+fn scalar_run_length<const N: usize>(pixels: &[Pixel<N>], pixel: Pixel<N>) -> usize {
+    pixels
+        .iter()
+        .position(|other| *other != pixel)
+        .unwrap_or(pixels.len())
+}
+
+/// Bytes of the channels of `pixels`
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn as_bytes<const N: usize>(pixels: &[Pixel<N>]) -> &[u8] {
+    // SAFETY: `Pixel<N>` is a transparent `[u8; N]`
+    unsafe { std::slice::from_raw_parts(pixels.as_ptr().cast(), std::mem::size_of_val(pixels)) }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    const LANES: usize = 32;
+
+    /// Counts the number of leading chunks of `bytes` equal to `pattern`, a multiple of 32 bytes
+    /// long
+    ///
+    /// # Safety
+    ///
+    /// The CPU must support AVX2.
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn equal_chunks(bytes: &[u8], pattern: &[u8]) -> usize {
+        bytes
+            .chunks_exact(pattern.len())
+            .take_while(|chunk| {
+                let mut equal = _mm256_set1_epi8(-1);
+                for (chunk, pattern) in chunk.chunks_exact(LANES).zip(pattern.chunks_exact(LANES)) {
+                    // SAFETY: Both slices are exactly `LANES` bytes long
+                    let (chunk, pattern) = unsafe {
+                        (
+                            _mm256_loadu_si256(chunk.as_ptr().cast()),
+                            _mm256_loadu_si256(pattern.as_ptr().cast()),
+                        )
+                    };
+                    equal = _mm256_and_si256(equal, _mm256_cmpeq_epi8(chunk, pattern));
+                }
+
+                _mm256_movemask_epi8(equal) == -1
+            })
+            .count()
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod sse2 {
+    use std::arch::x86_64::*;
+
+    const LANES: usize = 16;
+
+    /// Counts the number of leading chunks of `bytes` equal to `pattern`, a multiple of 16 bytes
+    /// long
+    ///
+    /// # Safety
+    ///
+    /// The CPU must support SSE2.
+    #[target_feature(enable = "sse2")]
+    pub(super) unsafe fn equal_chunks(bytes: &[u8], pattern: &[u8]) -> usize {
+        bytes
+            .chunks_exact(pattern.len())
+            .take_while(|chunk| {
+                let mut equal = _mm_set1_epi8(-1);
+                for (chunk, pattern) in chunk.chunks_exact(LANES).zip(pattern.chunks_exact(LANES)) {
+                    // SAFETY: Both slices are exactly `LANES` bytes long
+                    let (chunk, pattern) = unsafe {
+                        (
+                            _mm_loadu_si128(chunk.as_ptr().cast()),
+                            _mm_loadu_si128(pattern.as_ptr().cast()),
+                        )
+                    };
+                    equal = _mm_and_si128(equal, _mm_cmpeq_epi8(chunk, pattern));
+                }
+
+                _mm_movemask_epi8(equal) == 0xffff
+            })
+            .count()
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    const LANES: usize = 16;
+
+    /// Counts the number of leading chunks of `bytes` equal to `pattern`, a multiple of 16 bytes
+    /// long
+    ///
+    /// # Safety
+    ///
+    /// The CPU must support NEON.
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn equal_chunks(bytes: &[u8], pattern: &[u8]) -> usize {
+        bytes
+            .chunks_exact(pattern.len())
+            .take_while(|chunk| {
+                let mut equal = vdupq_n_u8(u8::MAX);
+                for (chunk, pattern) in chunk.chunks_exact(LANES).zip(pattern.chunks_exact(LANES)) {
+                    // SAFETY: Both slices are exactly `LANES` bytes long
+                    let (chunk, pattern) =
+                        unsafe { (vld1q_u8(chunk.as_ptr()), vld1q_u8(pattern.as_ptr())) };
+                    equal = vandq_u8(equal, vceqq_u8(chunk, pattern));
+                }
+
+                vminvq_u8(equal) == u8::MAX
+            })
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel::SupportedChannels;
+
+    const WIDTH: usize = 256;
+
+    /// Images with runs of every length: noise, horizontal bands, flat panels with thin borders,
+    /// and a flat background with scattered dots
+    fn corpus() -> Vec<Vec<Pixel<4>>> {
+        let mut state = 1u32;
+        let mut random = move || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        };
+
+        let noise = (0..WIDTH * WIDTH)
+            .map(|_| Pixel::rgba(random() % 4, 0, 0, 255))
+            .collect();
+        let bands = (0..WIDTH * WIDTH)
+            .map(|i| Pixel::rgba((i / 37 % 3) as u8, (i / 101) as u8, 0, 255))
+            .collect();
+        let panels = (0..WIDTH * WIDTH)
+            .map(|i| {
+                let (x, y) = (i % WIDTH, i / WIDTH);
+                match x % 64 < 2 || y % 32 < 2 {
+                    true => Pixel::rgba(20, 20, 20, 255),
+                    false => Pixel::rgba(200, (x / 64 * 40) as u8, (y / 32) as u8, 255),
+                }
+            })
+            .collect();
+        let dots = (0..WIDTH * WIDTH)
+            .map(|_| match random() {
+                0 => Pixel::rgba(0, 0, 0, 0),
+                _ => Pixel::rgba(240, 240, 240, 255),
+            })
+            .collect();
+
+        vec![noise, bands, panels, dots]
+    }
+
+    #[test]
+    fn finds_runs_of_any_length() {
+        let pixel = Pixel::rgba(1, 2, 3, 4);
+
+        for length in [0, 1, 31, 32, 33, 62, 63, 64, 100, 257] {
+            let mut pixels = vec![pixel; length];
+            assert_eq!(run_length(&pixels, pixel), length);
+
+            pixels.push(Pixel::rgba(1, 2, 3, 5));
+            pixels.push(pixel);
+            assert_eq!(run_length(&pixels, pixel), length);
+        }
+    }
+
+    #[test]
+    fn rgb_runs() {
+        let pixel = Pixel::rgb(9, 9, 9);
+        let mut pixels = vec![pixel; 70];
+        pixels[40] = Pixel::rgb(9, 9, 8);
+
+        assert_eq!(run_length(&pixels, pixel), 40);
+        assert_eq!(run_length(&pixels[41..], pixel), 29);
+    }
+
+    #[test]
+    fn every_scan_agrees() {
+        let pixel = Pixel::rgb(7, 8, 9);
+
+        // NB: A differing channel in every position of a chunk, and chunks at every byte offset
+        for position in 0..3 * CHUNK_SIZE + 5 {
+            for channel in 0..3 {
+                let mut pixels = vec![pixel; 4 * CHUNK_SIZE];
+                let mut rgb = pixel.as_inner_rgb();
+                rgb[channel] ^= 0x80;
+                pixels[position] = Pixel::rgb(rgb[0], rgb[1], rgb[2]);
+
+                for start in 0..3 {
+                    let pixels = &pixels[start..];
+                    let expected = folded_equal_chunks(pixels, pixel);
+                    assert_eq!(equal_chunks(pixels, pixel), expected);
+
+                    #[cfg(target_arch = "x86_64")]
+                    {
+                        let (bytes, pattern) = (as_bytes(pixels), [pixel; CHUNK_SIZE]);
+                        // SAFETY: SSE2 is part of the x86_64 baseline
+                        let chunks = unsafe { sse2::equal_chunks(bytes, as_bytes(&pattern)) };
+                        assert_eq!(chunks, expected);
+                    }
+
+                    assert_eq!(
+                        run_length(pixels, pixel),
+                        position.checked_sub(start).unwrap_or(pixels.len())
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn chunked_scan_matches_scalar_scan_on_corpus() {
+        for pixels in corpus() {
+            let mut i = 0;
+            while i < pixels.len() {
+                let length = run_length(&pixels[i..], pixels[i]);
+                assert_eq!(length, scalar_run_length(&pixels[i..], pixels[i]));
+                i += length;
+            }
+        }
+    }
+}
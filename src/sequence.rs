@@ -0,0 +1,343 @@
+//! Sequences of frames encoded against the previous frame, such as screen recordings
+//!
+//! A frame can be stored as a delta frame: the wrapping difference of each of its channels from
+//! the same pixel of the previous frame, encoded as a regular QOI image. Unchanged pixels become
+//! zero and small changes become small differences, which are encoded into long `QOI_OP_RUN`s and
+//! cheap `QOI_OP_DIFF`s. The layout of a sequence is, with all integers in big-endian:
+//!
+//! | Field                          | Size in bytes  |
+//! |--------------------------------|----------------|
+//! | Magic bytes `b"qoiv"`          | 4              |
+//! | Number of frames               | 4              |
+//! | Frames                         |                |
+//!
+//! Each frame starts with its kind as a `u8`, `0` for a key frame and `1` for a delta frame, and
+//! the size of its image as a `u32`, followed by the image encoded with [`encode`](crate::encode).
+//! The frame before the first one is taken to be all zeros, so any frame may be a delta frame.
+
+use crate::{
+    header::{ColorChannel, ColorSpace, Header},
+    io::{Reader, Take, Writer},
+    pixel::{Pixel, SupportedChannels},
+    Error, Result,
+};
+
+/// Magic bytes of a sequence
+const SEQUENCE_MAGIC: &[u8; 4] = b"qoiv";
+
+/// Kind of a frame stored as is
+const KEY_FRAME: u8 = 0;
+
+/// Kind of a frame stored as the difference from the previous frame
+const DELTA_FRAME: u8 = 1;
+
+/// Maximum number of frames allocated up front, before any of them are read
+const MAX_PREALLOCATED_FRAMES: usize = 1 << 10;
+
+/// Applies `op` to each pair of the first `channels` channels of `a` and `b`, keeping the other
+/// channels of `a`
+fn zip_channels<const N: usize>(
+    a: &[Pixel<N>],
+    b: &[Pixel<N>],
+    channels: ColorChannel,
+    op: impl Fn(u8, u8) -> u8,
+) -> Vec<Pixel<N>>
+where
+    Pixel<N>: SupportedChannels,
+{
+    let channels = channels as usize;
+
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| {
+            let (a, b) = (a.as_inner_rgba(), b.as_inner_rgba());
+            Pixel::from_inner_rgba(std::array::from_fn(|c| match c < channels {
+                true => op(a[c], b[c]),
+                false => a[c],
+            }))
+        })
+        .collect()
+}
+
+/// Encodes `frames`, each of `width` and `height`, into a sequence, then writing it into the
+/// provided `writer`.
+///
+/// Every frame is encoded both as a key frame and as a delta frame, keeping the smaller one.
+///
+/// The function returns the number of bytes written to the `writer`.
+///
+/// # Errors
+/// This function returns `Err` in one of the following cases:
+///
+/// 1. Either [`Writer::write_byte`] or [`Writer::write_from_slice`] fails.
+/// 2. The provided `width` and `height` differs from the length of any frame
+///    ([`Error::UnmatchedDataSize`])
+pub fn encode<const N: usize>(
+    writer: &mut impl Writer,
+    frames: &[impl AsRef<[Pixel<N>]>],
+    width: u32,
+    height: u32,
+    color_space: ColorSpace,
+) -> Result<usize>
+where
+    Pixel<N>: SupportedChannels,
+{
+    // NB: Every frame is checked before allocating the previous frame from the dimensions
+    let image_size = (width as usize).saturating_mul(height as usize);
+    if let Some(frame) = frames
+        .iter()
+        .find(|frame| frame.as_ref().len() != image_size)
+    {
+        return Err(Error::UnmatchedDataSize {
+            data_size: frame.as_ref().len(),
+            header_size: image_size,
+        });
+    }
+
+    let mut written = 0;
+
+    // Write header information
+    written += writer.write_from_slice(SEQUENCE_MAGIC)?;
+    written += writer.write_from_slice(&(frames.len() as u32).to_be_bytes())?;
+
+    let mut previous = vec![Pixel::<N>::from_inner_rgba([0; 4]); image_size];
+    for frame in frames {
+        let frame = frame.as_ref();
+
+        let mut key = vec![];
+        crate::encode(&mut key, frame, width, height, color_space)?;
+
+        let delta = zip_channels(frame, &previous, ColorChannel::Rgba, u8::wrapping_sub);
+        let mut delta_image = vec![];
+        crate::encode(&mut delta_image, &delta, width, height, color_space)?;
+
+        let (kind, image) = match delta_image.len() < key.len() {
+            true => (DELTA_FRAME, delta_image),
+            false => (KEY_FRAME, key),
+        };
+
+        written += writer.write_byte(kind)?;
+        written += writer.write_from_slice(&(image.len() as u32).to_be_bytes())?;
+        written += writer.write_from_slice(&image)?;
+
+        previous.copy_from_slice(frame);
+    }
+
+    Ok(written)
+}
+
+/// Decodes a sequence from the provided `reader`, returning the [`Header`] of its first frame and
+/// every frame.
+///
+/// # Errors
+/// This function returns `Err` in one of the following cases:
+///
+/// 1. Reading from the `reader` fails, including when the data ends early or the image of a frame
+///    runs past its size.
+/// 2. The magic bytes are invalid ([`Error::InvalidMagic`]), or a frame has an invalid kind
+///    ([`Error::InvalidFrameKind`])
+/// 3. Decoding the image of a frame fails, see [`decode`](crate::decode)
+/// 4. A frame differs in size from the first frame ([`Error::UnmatchedDataSize`])
+pub fn decode<const N: usize>(
+    reader: &mut impl Reader,
+) -> Result<(Option<Header>, Vec<Vec<Pixel<N>>>)>
+where
+    Pixel<N>: SupportedChannels,
+{
+    let mut magic = [0; 4];
+    reader.read_to_slice(&mut magic)?;
+    if &magic != SEQUENCE_MAGIC {
+        return Err(Error::InvalidMagic(magic));
+    }
+
+    let mut bytes = [0; 4];
+    reader.read_to_slice(&mut bytes)?;
+    let frame_count = u32::from_be_bytes(bytes) as usize;
+
+    // NB: The frame count is not trusted with the allocation, as the data may end early
+    let mut frames: Vec<Vec<Pixel<N>>> =
+        Vec::with_capacity(frame_count.min(MAX_PREALLOCATED_FRAMES));
+    let mut first_header = None;
+
+    for _ in 0..frame_count {
+        let kind = reader.read_byte()?;
+
+        // NB: The image is decoded within its size, skipping any bytes left after it
+        reader.read_to_slice(&mut bytes)?;
+        let mut data = Take::new(reader, u32::from_be_bytes(bytes) as u64);
+        let (header, image) = crate::decode::<N>(&mut data)?;
+        data.skip_remaining()?;
+
+        let first_header = first_header.get_or_insert(header);
+        if (header.width(), header.height()) != (first_header.width(), first_header.height()) {
+            return Err(Error::UnmatchedDataSize {
+                data_size: image.len(),
+                header_size: first_header.width() as usize * first_header.height() as usize,
+            });
+        }
+
+        let frame = match kind {
+            KEY_FRAME => image,
+            DELTA_FRAME => match frames.last() {
+                // NB: An RGB image stores no alpha, so the opaque alpha it decodes to is kept
+                Some(previous) => {
+                    zip_channels(&image, previous, header.channels(), u8::wrapping_add)
+                }
+                // NB: The frame before the first one is all zeros
+                None => image,
+            },
+            _ => return Err(Error::InvalidFrameKind(kind)),
+        };
+
+        frames.push(frame);
+    }
+
+    Ok((first_header, frames))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A flat "screen" with a block of "text" typed in and a moving "cursor"
+    fn screen_recording(width: u32, height: u32, count: u32) -> Vec<Vec<Pixel<4>>> {
+        let mut screen = vec![Pixel::rgba(240, 240, 240, 255); (width * height) as usize];
+
+        (0..count)
+            .map(|i| {
+                let (x, y) = ((i * 3) % width, (i * 2) % height);
+                screen[(y * width + x) as usize] = Pixel::rgba(20, 20, 20, 255);
+
+                let mut frame = screen.clone();
+                frame[((height - 1 - y) * width + x) as usize] = Pixel::rgba(0, 0, 255, 128);
+                frame
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trips() {
+        let (width, height) = (32, 24);
+        let frames = screen_recording(width, height, 10);
+
+        let mut buf = vec![];
+        let written = encode(&mut buf, &frames, width, height, ColorSpace::Srgb).unwrap();
+        assert_eq!(written, buf.len());
+
+        let (header, decoded) = decode::<4>(&mut buf.as_slice()).unwrap();
+        assert_eq!(header.unwrap().width(), width);
+        assert_eq!(decoded, frames);
+
+        let rgb: Vec<Vec<_>> = frames
+            .iter()
+            .map(|frame| {
+                frame
+                    .iter()
+                    .map(|pixel| Pixel::rgb(pixel.red(), pixel.green(), pixel.blue()))
+                    .collect()
+            })
+            .collect();
+
+        let mut buf = vec![];
+        encode(&mut buf, &rgb, width, height, ColorSpace::Srgb).unwrap();
+        assert_eq!(decode::<3>(&mut buf.as_slice()).unwrap().1, rgb);
+    }
+
+    #[test]
+    fn delta_frames_are_smaller() {
+        let (width, height) = (64, 48);
+        let frames: Vec<Vec<_>> = (0..5u8)
+            .map(|i| {
+                (0..width * height)
+                    .map(|p| Pixel::rgba((p % 251) as u8, (p / 7) as u8, i, 255))
+                    .collect()
+            })
+            .collect();
+
+        let mut buf = vec![];
+        encode(&mut buf, &frames, width, height, ColorSpace::Srgb).unwrap();
+
+        let mut independent = 0;
+        for frame in &frames {
+            independent +=
+                crate::encode(&mut vec![], frame, width, height, ColorSpace::Srgb).unwrap();
+        }
+
+        // NB: Only the first frame is a key frame, the others only change blue by `1`
+        assert!(
+            buf.len() * 3 < independent,
+            "{} >= {independent} / 3",
+            buf.len()
+        );
+        assert_eq!(decode::<4>(&mut buf.as_slice()).unwrap().1, frames);
+    }
+
+    #[test]
+    fn rejects_invalid_frames() {
+        let frames = screen_recording(4, 4, 2);
+
+        assert!(matches!(
+            encode(&mut vec![], &frames, 4, 5, ColorSpace::Srgb),
+            Err(Error::UnmatchedDataSize { .. })
+        ));
+        assert!(matches!(
+            encode::<4>(&mut vec![], &[vec![]], u32::MAX, u32::MAX, ColorSpace::Srgb),
+            Err(Error::UnmatchedDataSize { data_size: 0, .. })
+        ));
+
+        let mut buf = vec![];
+        encode(&mut buf, &frames, 4, 4, ColorSpace::Srgb).unwrap();
+        buf[8] = 2;
+
+        assert!(matches!(
+            decode::<4>(&mut buf.as_slice()),
+            Err(Error::InvalidFrameKind(2))
+        ));
+    }
+
+    #[test]
+    fn rgb_deltas_keep_alpha_opaque() {
+        let (width, height) = (8, 8);
+        let frames: Vec<Vec<_>> = (0..3u8)
+            .map(|i| vec![Pixel::rgb(10, 20, 30 + i); (width * height) as usize])
+            .collect();
+
+        let mut buf = vec![];
+        encode(&mut buf, &frames, width, height, ColorSpace::Srgb).unwrap();
+        // NB: The second frame is a delta frame, whose opaque alpha is not a difference
+        let second = 13 + u32::from_be_bytes(buf[9..13].try_into().unwrap()) as usize;
+        assert_eq!(buf[second], DELTA_FRAME);
+
+        let (_, decoded) = decode::<4>(&mut buf.as_slice()).unwrap();
+        let expected: Vec<Vec<_>> = frames
+            .iter()
+            .map(|frame| frame.iter().map(|&pixel| Pixel::<4>::from(pixel)).collect())
+            .collect();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn decodes_frames_within_their_size() {
+        let frames = screen_recording(4, 4, 2);
+
+        let mut buf = vec![];
+        encode(&mut buf, &frames, 4, 4, ColorSpace::Srgb).unwrap();
+
+        // NB: Size of the first frame follows its kind
+        let size = u32::from_be_bytes(buf[9..13].try_into().unwrap());
+        let end = 13 + size as usize;
+
+        // Bytes left after an image are skipped
+        let mut padded = buf.clone();
+        padded[9..13].copy_from_slice(&(size + 3).to_be_bytes());
+        padded.splice(end..end, [0xaa; 3]);
+        assert_eq!(decode::<4>(&mut padded.as_slice()).unwrap().1, frames);
+
+        // An image running past its size is rejected
+        buf[9..13].copy_from_slice(&(size - 1).to_be_bytes());
+        assert!(matches!(
+            decode::<4>(&mut buf.as_slice()),
+            Err(Error::IoError(error)) if error.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+    }
+}
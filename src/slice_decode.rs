@@ -0,0 +1,363 @@
+//! A fast path decoder working on an in-memory QOI file
+//!
+//! Unlike [`decode`](crate::decode), which pulls bytes one `QOI_OP` at a time through a
+//! [`Reader`](crate::io::Reader) and builds [`Pixel`](crate::Pixel)s, this decoder reads straight
+//! from a byte slice and writes raw channels into the output buffer. Each `QOI_OP` is dispatched
+//! through a 256-entry table indexed by its first byte, which holds its size, its kind and the
+//! pre-decoded payload of the first byte. All but the last few ops are read from a fixed-size
+//! window of the input with a single bounds check, and pixels are packed into a `u32` whose
+//! channels are added all at once.
+//!
+//! NB: Decoding is bound by mispredicted branches on the kind of each op, so the table is only
+//! NB: branched on once per op. Computing every candidate pixel and selecting one with masks
+//! NB: instead avoids those branches, but was no faster than `decode`.
+
+use crate::{
+    constants::{
+        QOI_END_MARKER, QOI_MAX_RUN, QOI_OP_DIFF, QOI_OP_INDEX, QOI_OP_LUMA, QOI_OP_MASK,
+        QOI_OP_RGB, QOI_OP_RGBA, QOI_OP_RUN,
+    },
+    header::Header,
+    pixel::{Pixel, SupportedChannels},
+    Error, Result,
+};
+
+/// Maximum size of a single `QOI_OP` in bytes, ie. a `QOI_OP_RGBA`
+const MAX_OP_SIZE: usize = 5;
+
+/// Kind of a `QOI_OP`, as told by its first byte
+#[derive(Clone, Copy)]
+enum Kind {
+    Index,
+    Diff,
+    Luma,
+    Rgb,
+    Rgba,
+    Run,
+}
+
+/// Everything about a `QOI_OP` derived from its first byte. Pixels are packed into a `u32` in the
+/// order R, G, B, A from the least significant byte.
+#[derive(Clone, Copy)]
+struct Op {
+    kind: Kind,
+
+    /// Number of bytes of the op, including the first byte
+    size: u8,
+
+    /// Index into the seen pixels of a `QOI_OP_INDEX`, wrapping differences added to the previous
+    /// pixel by a `QOI_OP_DIFF` or `QOI_OP_LUMA`, or length of a `QOI_OP_RUN`
+    payload: u32,
+}
+
+/// Dispatch table of all `QOI_OP`s, indexed by their first byte
+static OPS: [Op; 256] = build_ops();
+
+const fn build_ops() -> [Op; 256] {
+    let mut ops = [Op {
+        kind: Kind::Index,
+        size: 1,
+        payload: 0,
+    }; 256];
+
+    let mut tag = 0;
+    while tag < 256 {
+        let byte = tag as u8;
+        let payload = byte & !QOI_OP_MASK;
+
+        ops[tag] = match byte {
+            QOI_OP_RGB => Op {
+                kind: Kind::Rgb,
+                size: 4,
+                payload: 0,
+            },
+            QOI_OP_RGBA => Op {
+                kind: Kind::Rgba,
+                size: 5,
+                payload: 0,
+            },
+            _ => match byte & QOI_OP_MASK {
+                QOI_OP_INDEX => Op {
+                    kind: Kind::Index,
+                    size: 1,
+                    payload: payload as u32,
+                },
+                QOI_OP_DIFF => Op {
+                    kind: Kind::Diff,
+                    size: 1,
+                    payload: u32::from_le_bytes([
+                        (payload >> 4 & 0b11).wrapping_sub(2),
+                        (payload >> 2 & 0b11).wrapping_sub(2),
+                        (payload & 0b11).wrapping_sub(2),
+                        0,
+                    ]),
+                },
+                QOI_OP_LUMA => {
+                    let diff_green = payload.wrapping_sub(32);
+
+                    Op {
+                        kind: Kind::Luma,
+                        size: 2,
+                        // NB: `dr_dg` and `db_dg` from the second byte are still to be added
+                        payload: u32::from_le_bytes([
+                            diff_green.wrapping_sub(8),
+                            diff_green,
+                            diff_green.wrapping_sub(8),
+                            0,
+                        ]),
+                    }
+                }
+                QOI_OP_RUN => Op {
+                    kind: Kind::Run,
+                    size: 1,
+                    payload: payload as u32 + 1,
+                },
+                _ => unreachable!(),
+            },
+        };
+
+        tag += 1;
+    }
+
+    ops
+}
+
+/// Adds each byte of `a` and `b` with wrapping, without carrying into the next byte
+fn wrapping_add_channels(a: u32, b: u32) -> u32 {
+    ((a & 0x7f7f_7f7f) + (b & 0x7f7f_7f7f)) ^ ((a ^ b) & 0x8080_8080)
+}
+
+/// Index of a `pixel` packed in the order R, G, B, A from the least significant byte in the seen
+/// pixels
+fn index_hash(pixel: u32) -> usize {
+    let [red, green, blue, alpha] = pixel.to_le_bytes();
+
+    // NB: `64` divides `256`, so the sum can wrap around before taking the remainder
+    let hash = red
+        .wrapping_mul(3)
+        .wrapping_add(green.wrapping_mul(5))
+        .wrapping_add(blue.wrapping_mul(7))
+        .wrapping_add(alpha.wrapping_mul(11));
+
+    (hash % 64) as usize
+}
+
+/// Decodes a QOI image held entirely in `bytes`, returning its [`Header`] and the raw channels of
+/// its pixels.
+///
+/// The output contains `N` bytes per pixel in the order R, G, B(, A), regardless of the number of
+/// `channels` specified in the header, with the same conversion as [`decode`](crate::decode).
+/// Options such as [`DecodeOptions`](crate::DecodeOptions) are not supported on this path.
+///
+/// # Errors
+/// This function returns `Err` in the same cases as [`decode`](crate::decode), where running out of
+/// data is reported as an [`Error::IoError`] of kind [`std::io::ErrorKind::UnexpectedEof`].
+pub fn decode_from_slice<const N: usize>(bytes: &[u8]) -> Result<(Header, Vec<u8>)>
+where
+    Pixel<N>: SupportedChannels,
+{
+    let unexpected_eof = || Error::IoError(std::io::ErrorKind::UnexpectedEof.into());
+
+    // Read header information
+    let header = {
+        let header_bytes = bytes.get(..Header::SIZE).ok_or_else(unexpected_eof)?;
+        Header::from_bytes(header_bytes.try_into().unwrap())?
+    };
+
+    let image_size = (header.width() as usize).saturating_mul(header.height() as usize);
+
+    // NB: Every byte decodes to at most a full run, so a header claiming more pixels is rejected
+    // NB: before allocating for them
+    let max_image_size = (bytes.len() - Header::SIZE).saturating_mul(QOI_MAX_RUN);
+    if image_size > max_image_size {
+        return Err(unexpected_eof());
+    }
+
+    let output_size = image_size.saturating_mul(N);
+    let mut output = vec![0; output_size];
+
+    let mut previous_pixel = u32::from_le_bytes([0, 0, 0, 255]);
+    let mut seen_pixels = [0u32; 64];
+
+    let mut position = Header::SIZE;
+
+    let mut i = 0;
+    while i < output.len() {
+        // Fetch a window large enough for any op with a single bounds check
+        let window: [u8; MAX_OP_SIZE] = match bytes.get(position..position + MAX_OP_SIZE) {
+            Some(window) => window.try_into().unwrap(),
+            None => {
+                let rest = bytes.get(position..).unwrap_or_default();
+                let mut tail = [0; MAX_OP_SIZE];
+                tail[..rest.len()].copy_from_slice(rest);
+                tail
+            }
+        };
+        let [tag, a, b, c, d] = window;
+        let op = OPS[tag as usize];
+
+        let pixel = match op.kind {
+            Kind::Index => seen_pixels[op.payload as usize],
+            Kind::Diff => wrapping_add_channels(previous_pixel, op.payload),
+            Kind::Luma => {
+                let diff = u32::from_le_bytes([a >> 4, 0, a & 0x0f, 0]);
+                wrapping_add_channels(wrapping_add_channels(previous_pixel, op.payload), diff)
+            }
+            Kind::Rgb => u32::from_le_bytes([a, b, c, 0]) | previous_pixel & 0xff00_0000,
+            Kind::Rgba => u32::from_le_bytes([a, b, c, d]),
+            Kind::Run => {
+                position += 1;
+                if position > bytes.len() {
+                    return Err(unexpected_eof());
+                }
+
+                // NB: Runs past the end of the image are cut short
+                let end = output.len().min(i + op.payload as usize * N);
+                let pixel = &previous_pixel.to_le_bytes()[..N];
+                for output_pixel in output[i..end].chunks_exact_mut(N) {
+                    output_pixel.copy_from_slice(pixel);
+                }
+                i = end;
+
+                continue;
+            }
+        };
+
+        position += op.size as usize;
+        if position > bytes.len() {
+            return Err(unexpected_eof());
+        }
+
+        seen_pixels[index_hash(pixel)] = pixel;
+        output[i..i + N].copy_from_slice(&pixel.to_le_bytes()[..N]);
+        previous_pixel = pixel;
+        i += N;
+    }
+
+    // Check the end marker
+    let end_marker = bytes
+        .get(position..position + QOI_END_MARKER.len())
+        .ok_or_else(unexpected_eof)?;
+    if end_marker != QOI_END_MARKER {
+        return Err(Error::InvalidEndMarker(end_marker.try_into().unwrap()));
+    }
+
+    Ok((header, output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode, encode, header::ColorSpace};
+
+    /// An image exercising every `QOI_OP`, including alpha changes and wrapping differences
+    fn all_ops() -> Vec<Pixel<4>> {
+        let mut pixels = vec![];
+
+        for i in 0..=255u8 {
+            pixels.push(Pixel::rgba(i, i.wrapping_mul(3), 255 - i, 255));
+            pixels.push(Pixel::rgba(i, i.wrapping_mul(3), 255 - i, 255));
+            pixels.push(Pixel::rgba(
+                i.wrapping_add(1),
+                i.wrapping_mul(3),
+                254u8.wrapping_sub(i),
+                255,
+            ));
+            pixels.push(Pixel::rgba(
+                i.wrapping_add(20),
+                i.wrapping_mul(3).wrapping_add(17),
+                254u8.wrapping_sub(i),
+                255,
+            ));
+            pixels.push(Pixel::rgba(i, 0, i, i));
+        }
+        pixels.extend([Pixel::rgba(0, 0, 0, 0); 100]);
+
+        pixels
+    }
+
+    #[test]
+    fn matches_decode() {
+        let pixels = all_ops();
+
+        let mut buf = vec![];
+        encode(
+            &mut buf,
+            &pixels,
+            20,
+            pixels.len() as u32 / 20,
+            ColorSpace::Srgb,
+        )
+        .unwrap();
+
+        let (header, bytes) = decode_from_slice::<4>(&buf).unwrap();
+        let (expected_header, expected) = decode::<4>(&mut buf.as_slice()).unwrap();
+
+        assert_eq!(header, expected_header);
+        assert_eq!(
+            bytes,
+            expected
+                .iter()
+                .flat_map(|pixel| pixel.as_inner_rgba())
+                .collect::<Vec<_>>()
+        );
+
+        let (_, bytes) = decode_from_slice::<3>(&buf).unwrap();
+        let (_, expected) = decode::<3>(&mut buf.as_slice()).unwrap();
+
+        assert_eq!(
+            bytes,
+            expected
+                .iter()
+                .flat_map(|pixel| pixel.as_inner_rgb())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn rejects_every_truncation() {
+        let pixels = all_ops();
+
+        let mut buf = vec![];
+        encode(
+            &mut buf,
+            &pixels,
+            20,
+            pixels.len() as u32 / 20,
+            ColorSpace::Srgb,
+        )
+        .unwrap();
+
+        for length in 0..buf.len() {
+            assert!(matches!(
+                decode_from_slice::<4>(&buf[..length]),
+                Err(Error::IoError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_end_marker() {
+        let mut buf = vec![];
+        encode(&mut buf, &[Pixel::rgb(1, 2, 3)], 1, 1, ColorSpace::Srgb).unwrap();
+        let end = buf.len();
+        buf[end - 8] = 0xff;
+
+        assert!(matches!(
+            decode_from_slice::<3>(&buf),
+            Err(Error::InvalidEndMarker([0xff, 0, 0, 0, 0, 0, 0, 1]))
+        ));
+    }
+
+    #[test]
+    fn rejects_oversized_header_before_allocating() {
+        let mut buf = vec![];
+        encode(&mut buf, &[Pixel::rgb(1, 2, 3)], 1, 1, ColorSpace::Srgb).unwrap();
+        buf[4..12].fill(0xff);
+
+        assert!(matches!(
+            decode_from_slice::<4>(&buf),
+            Err(Error::IoError(_))
+        ));
+    }
+}
@@ -0,0 +1,580 @@
+//! Downscaled thumbnails decoded straight from a QOI image
+//!
+//! [`thumbnail`] decodes an image row by row and resamples each row as soon as it is decoded, so
+//! only the rows the [`Filter`] spans are held at any time, never the full-resolution image. The
+//! resampling is separable: rows are first resampled to the width of the thumbnail, then columns
+//! of those rows to its height.
+//!
+//! Pixels are filtered with alpha premultiplied, so the colour of transparent pixels does not
+//! bleed into their neighbours, and in the color space of the image as stored.
+//!
+//! Images of the extended profile stored in any [`Traversal`] but [`Traversal::RowMajor`] only have
+//! their rows once every pixel is decoded, so they are rejected rather than decoded in full.
+
+use std::collections::VecDeque;
+
+use crate::{
+    constants::QOI_END_MARKER,
+    decode::{DecoderState, MAX_PREALLOCATED_PIXELS},
+    header::Header,
+    io::Reader,
+    pixel::{Pixel, SupportedChannels},
+    profile::{self, Extensions},
+    traversal::Traversal,
+    Error, Result,
+};
+
+/// Maximum number of weights stored for the spans along one axis, before any row is decoded
+const MAX_STORED_WEIGHTS: usize = 1 << 20;
+
+/// Filter used to resample the image
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Filter {
+    /// Averages the pixels covered by each thumbnail pixel, which is fast and exact for whole
+    /// ratios, but blocky otherwise
+    Box,
+
+    /// Triangle filter, which is smoother than [`Filter::Box`] but slightly blurry
+    Bilinear,
+
+    /// Windowed sinc over 3 lobes, which is the sharpest but may ring around hard edges
+    #[default]
+    Lanczos3,
+}
+
+impl Filter {
+    /// Half width of the filter, in pixels of the thumbnail
+    fn radius(self) -> f32 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Bilinear => 1.0,
+            Filter::Lanczos3 => 3.0,
+        }
+    }
+
+    /// Weight of a pixel at `x` pixels of the thumbnail from the center
+    fn weight(self, x: f32) -> f32 {
+        match self {
+            Filter::Box => match (-0.5..0.5).contains(&x) {
+                true => 1.0,
+                false => 0.0,
+            },
+            Filter::Bilinear => (1.0 - x.abs()).max(0.0),
+            Filter::Lanczos3 => match x.abs() < 3.0 {
+                true => lanczos3(x),
+                false => 0.0,
+            },
+        }
+    }
+}
+
+/// `sinc(x) * sinc(x / 3)`, with a single sine
+fn lanczos3(x: f32) -> f32 {
+    if x == 0.0 {
+        return 1.0;
+    }
+
+    // NB: `sin(3t) = 3 sin(t) - 4 sin(t)^3`, where `t` is `πx / 3`
+    let t = x * std::f32::consts::FRAC_PI_3;
+    let sin = t.sin();
+    sin * (3.0 * sin - 4.0 * sin * sin * sin) / (3.0 * t * t)
+}
+
+/// Dimensions of the thumbnail of an image of `width` and `height`, scaled down to fit within
+/// `max_size` on either side while keeping its aspect ratio. Images already small enough keep their
+/// dimensions, and no side is shorter than a pixel.
+pub fn thumbnail_size(width: u32, height: u32, max_size: u32) -> (u32, u32) {
+    let (longest, max_size) = (width.max(height) as u64, max_size.max(1) as u64);
+    if longest <= max_size {
+        return (width, height);
+    }
+
+    let scale = |side: u32| match side {
+        0 => 0,
+        _ => ((side as u64 * max_size + longest / 2) / longest).max(1) as u32,
+    };
+    (scale(width), scale(height))
+}
+
+/// Decodes the QOI image from the provided `reader` into a thumbnail fitting within `max_size` on
+/// either side (see [`thumbnail_size`]), resampled with `filter`. Returns a [`Header`] holding the
+/// dimensions of the thumbnail along with its pixels.
+///
+/// The whole image is consumed, including its end marker.
+///
+/// # Errors
+/// See [`decode`](crate::decode). Images not stored row by row fail with
+/// [`Error::UnsupportedTraversal`].
+pub fn thumbnail<const N: usize>(
+    reader: &mut impl Reader,
+    max_size: u32,
+    filter: Filter,
+) -> Result<(Header, Vec<Pixel<N>>)>
+where
+    Pixel<N>: SupportedChannels,
+{
+    let mut rows = Rows::new(reader)?;
+    let header = rows.header;
+    let (width, height) = (header.width(), header.height());
+    let (thumbnail_width, thumbnail_height) = thumbnail_size(width, height, max_size);
+
+    let columns = Weights::new(filter, width, thumbnail_width);
+    let lines = Weights::new(filter, height, thumbnail_height);
+
+    // NB: Rows resampled to the thumbnail width, from row `first` of the image onwards, which are
+    // NB: dropped once no line of the thumbnail spans them anymore
+    let mut window: VecDeque<Vec<[f32; 4]>> = VecDeque::new();
+    let mut first = 0;
+
+    // NB: The thumbnail is no larger than the image, whose size is not trusted with the allocation
+    let thumbnail_pixels = thumbnail_width as usize * thumbnail_height as usize;
+    let mut pixels = Vec::with_capacity(thumbnail_pixels.min(MAX_PREALLOCATED_PIXELS));
+    let mut line = 0;
+
+    // NB: Rows of an image without columns hold no data, however many the header claims
+    let decoded_rows = match width {
+        0 => 0,
+        _ => height as usize,
+    };
+    for y in 0..decoded_rows {
+        let row = rows.next_row()?;
+        window.push_back(columns.resample(|x| premultiply(row[x])));
+
+        // Emit every line of the thumbnail spanning no row past this one
+        while line < lines.len && lines.span(line).end <= y + 1 {
+            let span = lines.span(line);
+            pixels.extend(
+                (0..thumbnail_width as usize)
+                    .map(|x| unpremultiply(lines.apply(&span, |i| window[i - first][x]))),
+            );
+            line += 1;
+
+            // NB: The spans of later lines never start before the span of an earlier one
+            let start = match line < lines.len {
+                true => lines.span(line).start,
+                false => y + 1,
+            };
+            while first < start && !window.is_empty() {
+                window.pop_front();
+                first += 1;
+            }
+        }
+    }
+
+    rows.finish()?;
+
+    let header = Header::new(
+        thumbnail_width,
+        thumbnail_height,
+        header.channels(),
+        header.color_space(),
+    );
+
+    Ok((header, pixels))
+}
+
+/// Converts a pixel into premultiplied `[r, g, b, a]` with every channel within `0.0..=1.0`
+fn premultiply(rgba: [u8; 4]) -> [f32; 4] {
+    let [r, g, b, a] = rgba.map(|channel| channel as f32 / 255.0);
+    [r * a, g * a, b * a, a]
+}
+
+/// Converts filtered premultiplied channels back into a pixel, clamping any overshoot of the filter
+fn unpremultiply<const N: usize>([r, g, b, a]: [f32; 4]) -> Pixel<N>
+where
+    Pixel<N>: SupportedChannels,
+{
+    let alpha = (a * 255.0).round().clamp(0.0, 255.0);
+    if alpha == 0.0 {
+        return Pixel::from_inner_rgba([0; 4]);
+    }
+
+    let channel = |c: f32| (c / a * 255.0).round().clamp(0.0, 255.0) as u8;
+    Pixel::from_inner_rgba([channel(r), channel(g), channel(b), alpha as u8])
+}
+
+/// Span of pixels of the image contributing to a single pixel of the thumbnail
+#[derive(Clone, Copy, Debug)]
+struct Span {
+    /// First pixel of the image within the span
+    start: usize,
+
+    /// One past the last pixel of the image within the span
+    end: usize,
+
+    /// Center of the thumbnail pixel, in pixels of the image
+    center: f32,
+
+    /// Index of the weight of the first pixel in the stored weights, if any
+    offset: usize,
+}
+
+/// Spans of every pixel of the thumbnail along one axis
+///
+/// NB: The header claims both the number of spans and how many pixels each covers, so spans and
+/// NB: their weights are only stored up to [`MAX_STORED_WEIGHTS`], and computed as they are
+/// NB: applied beyond.
+struct Weights {
+    filter: Filter,
+
+    /// Number of pixels of the image
+    size: usize,
+
+    /// Number of pixels of the thumbnail, ie. of spans
+    len: usize,
+
+    /// Number of pixels of the image per pixel of the thumbnail
+    scale: f32,
+
+    /// Number of pixels of the image the filter is stretched over per pixel of the thumbnail
+    stretch: f32,
+
+    /// Every span along with the weight of each pixel of every span in turn, unless there are too
+    /// many to store
+    stored: Option<(Vec<Span>, Vec<f32>)>,
+}
+
+impl Weights {
+    fn new(filter: Filter, size: u32, thumbnail_size: u32) -> Self {
+        let scale = size as f32 / thumbnail_size as f32;
+        let mut weights = Self {
+            filter,
+            size: size as usize,
+            len: thumbnail_size as usize,
+            scale,
+            // NB: The filter is stretched over the pixels of the image covered by a thumbnail pixel
+            stretch: scale.max(1.0),
+            stored: None,
+        };
+
+        // NB: Every span has at least one weight, so at most as many spans as weights are computed
+        let mut spans = vec![];
+        let mut offset = 0;
+        for i in 0..weights.len {
+            let span = Span {
+                offset,
+                ..weights.computed_span(i)
+            };
+            offset += span.end - span.start;
+            if offset > MAX_STORED_WEIGHTS {
+                return weights;
+            }
+            spans.push(span);
+        }
+
+        let stored = spans
+            .iter()
+            .flat_map(|span| weights.computed(span))
+            .collect();
+        weights.stored = Some((spans, stored));
+
+        weights
+    }
+
+    /// Span of the `i`th pixel of the thumbnail
+    fn span(&self, i: usize) -> Span {
+        match &self.stored {
+            Some((spans, _)) => spans[i],
+            None => self.computed_span(i),
+        }
+    }
+
+    /// Span of the `i`th pixel of the thumbnail, without any stored weights
+    fn computed_span(&self, i: usize) -> Span {
+        let support = self.filter.radius() * self.stretch;
+
+        let center = (i as f32 + 0.5) * self.scale;
+        let start = ((center - support).floor().max(0.0) as usize).min(self.size - 1);
+        let end = ((center + support).ceil() as usize).clamp(start + 1, self.size);
+
+        Span {
+            start,
+            end,
+            center,
+            offset: 0,
+        }
+    }
+
+    /// Weights of the pixels of the `span`, before normalising
+    fn computed(&self, span: &Span) -> impl Iterator<Item = f32> + '_ {
+        let Span {
+            start, end, center, ..
+        } = *span;
+        (start..end).map(move |i| self.filter.weight((i as f32 + 0.5 - center) / self.stretch))
+    }
+
+    /// Sums the pixels of the `span`, given by their index in the image, by their normalised
+    /// weights
+    fn apply(&self, span: &Span, pixel: impl Fn(usize) -> [f32; 4]) -> [f32; 4] {
+        match &self.stored {
+            Some((_, stored)) => {
+                let weights = &stored[span.offset..span.offset + span.end - span.start];
+                weighted_sum(span, weights.iter().copied(), pixel)
+            }
+            None => weighted_sum(span, self.computed(span), pixel),
+        }
+    }
+
+    /// Resamples a row of the image, given by the pixel at each index, to the thumbnail size
+    fn resample(&self, pixel: impl Fn(usize) -> [f32; 4]) -> Vec<[f32; 4]> {
+        (0..self.len)
+            .map(|i| self.apply(&self.span(i), &pixel))
+            .collect()
+    }
+}
+
+/// Sums the pixels of the `span` by their `weights`, normalised so that they add up to `1`
+fn weighted_sum(
+    span: &Span,
+    weights: impl Iterator<Item = f32>,
+    pixel: impl Fn(usize) -> [f32; 4],
+) -> [f32; 4] {
+    let mut sum = [0.0; 4];
+    let mut total = 0.0;
+
+    for (i, weight) in (span.start..span.end).zip(weights) {
+        let pixel = pixel(i);
+        for channel in 0..4 {
+            sum[channel] += pixel[channel] * weight;
+        }
+        total += weight;
+    }
+
+    // NB: Spans whose weights cancel out, at most at the edges, fall back to their nearest pixel
+    match f32::abs(total) > f32::EPSILON {
+        true => sum.map(|channel| channel / total),
+        false => pixel((span.center as usize).clamp(span.start, span.end - 1)),
+    }
+}
+
+/// Rows of an image decoded one at a time, as stored before any conversion
+struct Rows<'a, R> {
+    reader: &'a mut R,
+    header: Header,
+    extensions: Extensions,
+    state: DecoderState,
+
+    /// Pixel of an unfinished run along with the number of pixels left in it
+    run: (Pixel<4>, usize),
+}
+
+impl<'a, R: Reader> Rows<'a, R> {
+    fn new(reader: &'a mut R) -> Result<Self> {
+        let (header, profile, extensions) = profile::read_header(reader)?;
+
+        if extensions.traversal != Traversal::RowMajor {
+            return Err(Error::UnsupportedTraversal(extensions.traversal));
+        }
+
+        Ok(Self {
+            reader,
+            header,
+            extensions,
+            state: DecoderState::with_profile(profile),
+            run: (Pixel::default(), 0),
+        })
+    }
+
+    /// Decodes the next row as `[r, g, b, a]`
+    fn next_row(&mut self) -> Result<Vec<[u8; 4]>> {
+        let width = self.header.width() as usize;
+
+        // NB: The width is not trusted with the allocation, as the data may end early
+        let mut row = Vec::with_capacity(width.min(MAX_PREALLOCATED_PIXELS));
+        while row.len() < width {
+            if self.run.1 == 0 {
+                self.run = self.state.decode_op(self.reader)?;
+            }
+
+            // NB: Runs may carry on into the following rows
+            let count = self.run.1.min(width - row.len());
+            row.extend(std::iter::repeat_n(
+                self.extensions
+                    .transform
+                    .inverse(self.run.0)
+                    .as_inner_rgba(),
+                count,
+            ));
+            self.run.1 -= count;
+        }
+
+        Ok(row)
+    }
+
+    /// Checks the end marker following the last row
+    fn finish(self) -> Result<()> {
+        let mut end_marker = [0; 8];
+        self.reader.read_to_slice(&mut end_marker)?;
+
+        if &end_marker != QOI_END_MARKER {
+            return Err(Error::InvalidEndMarker(end_marker));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{encode, encode_with_options, ColorSpace, EncodeOptions, Profile};
+
+    const FILTERS: [Filter; 3] = [Filter::Box, Filter::Bilinear, Filter::Lanczos3];
+
+    fn encoded(pixels: &[Pixel<4>], width: u32, height: u32) -> Vec<u8> {
+        let mut buf = vec![];
+        encode(&mut buf, pixels, width, height, ColorSpace::Srgb).unwrap();
+        buf
+    }
+
+    #[test]
+    fn fits_within_max_size() {
+        assert_eq!(thumbnail_size(1024, 768, 256), (256, 192));
+        assert_eq!(thumbnail_size(768, 1024, 256), (192, 256));
+        assert_eq!(thumbnail_size(100, 50, 256), (100, 50));
+        assert_eq!(thumbnail_size(10000, 3, 100), (100, 1));
+        assert_eq!(thumbnail_size(10, 10, 0), (1, 1));
+        assert_eq!(thumbnail_size(0, 1000, 10), (0, 10));
+    }
+
+    #[test]
+    fn flat_images_stay_flat() {
+        let pixel = Pixel::rgba(200, 100, 50, 180);
+        let buf = encoded(&vec![pixel; 300 * 200], 300, 200);
+
+        for filter in FILTERS {
+            let (header, pixels) = thumbnail::<4>(&mut buf.as_slice(), 64, filter).unwrap();
+
+            assert_eq!((header.width(), header.height()), (64, 43));
+            assert!(pixels.iter().all(|&p| p == pixel), "{filter:?}");
+        }
+    }
+
+    #[test]
+    fn box_filter_averages_blocks() {
+        // NB: 2x2 checkerboard of black and white, averaging to grey over every 2x2 block
+        let (width, height) = (64, 32);
+        let pixels: Vec<_> = (0..width * height)
+            .map(|i| match (i % width + i / width) % 2 {
+                0 => Pixel::rgba(0, 0, 0, 255),
+                _ => Pixel::rgba(254, 254, 254, 255),
+            })
+            .collect();
+
+        let buf = encoded(&pixels, width, height);
+        let (_, thumbnail) = thumbnail::<3>(&mut buf.as_slice(), 32, Filter::Box).unwrap();
+
+        assert_eq!(thumbnail, vec![Pixel::rgb(127, 127, 127); 32 * 16]);
+    }
+
+    #[test]
+    fn transparent_colours_do_not_bleed() {
+        let (width, height) = (8, 8);
+        let pixels: Vec<_> = (0..width * height)
+            .map(|i| match i % width < 4 {
+                true => Pixel::rgba(255, 0, 0, 255),
+                false => Pixel::rgba(0, 255, 0, 0),
+            })
+            .collect();
+
+        let buf = encoded(&pixels, width, height);
+        for filter in FILTERS {
+            let (_, thumbnail) = thumbnail::<4>(&mut buf.as_slice(), 2, filter).unwrap();
+
+            for pixel in thumbnail {
+                assert!(pixel.alpha() == 0 || pixel.green() == 0, "{filter:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_row_major_images_only() {
+        let (width, height) = (40, 30);
+        let pixels: Vec<_> = (0..width * height)
+            .map(|i| Pixel::rgba((i % width * 6) as u8, (i / width * 8) as u8, 90, 255))
+            .collect();
+
+        let expected = thumbnail::<4>(
+            &mut encoded(&pixels, width, height).as_slice(),
+            16,
+            Filter::Lanczos3,
+        )
+        .unwrap();
+
+        for traversal in [Traversal::RowMajor, Traversal::Hilbert] {
+            let options = EncodeOptions {
+                profile: Profile::Extended,
+                traversal,
+                ..Default::default()
+            };
+            let mut buf = vec![];
+            encode_with_options(&mut buf, &pixels, width, height, ColorSpace::Srgb, &options)
+                .unwrap();
+
+            let actual = thumbnail::<4>(&mut buf.as_slice(), 16, Filter::Lanczos3);
+            match traversal {
+                Traversal::RowMajor => assert_eq!(actual.unwrap(), expected),
+                _ => assert!(matches!(
+                    actual,
+                    Err(Error::UnsupportedTraversal(Traversal::Hilbert))
+                )),
+            }
+        }
+    }
+
+    #[test]
+    fn computes_weights_beyond_the_stored_ones() {
+        let (width, height) = (64, 48);
+        let pixels: Vec<_> = (0..width * height)
+            .map(|i| Pixel::rgba((i * 37 % 251) as u8, (i / width * 5) as u8, 90, 255))
+            .collect();
+        let row = |x: usize| premultiply(pixels[x].as_inner_rgba());
+
+        for filter in FILTERS {
+            let stored = Weights::new(filter, width, 10);
+            let mut computed = Weights::new(filter, width, 10);
+            assert!(computed.stored.take().is_some());
+
+            for (stored, computed) in stored.resample(row).iter().zip(computed.resample(row)) {
+                for (a, b) in stored.iter().zip(computed) {
+                    assert!((a - b).abs() < 1e-6, "{filter:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_image() {
+        let buf = encoded(&[Pixel::rgba(1, 2, 3, 4); 16], 4, 4);
+
+        assert!(thumbnail::<4>(&mut &buf[..buf.len() - 1], 2, Filter::Box).is_err());
+    }
+
+    #[test]
+    fn skips_rows_without_columns() {
+        let mut buf = b"qoif".to_vec();
+        buf.extend(0u32.to_be_bytes());
+        buf.extend(u32::MAX.to_be_bytes());
+        buf.extend([4, 0]);
+        buf.extend(QOI_END_MARKER);
+
+        let (header, pixels) = thumbnail::<4>(&mut buf.as_slice(), 16, Filter::Lanczos3).unwrap();
+        assert_eq!((header.width(), header.height()), (0, 16));
+        assert!(pixels.is_empty());
+    }
+
+    #[test]
+    fn rejects_huge_header_before_allocating() {
+        let mut buf = b"qoif".to_vec();
+        buf.extend(u32::MAX.to_be_bytes());
+        buf.extend(u32::MAX.to_be_bytes());
+        buf.extend([4, 0]);
+
+        for filter in FILTERS {
+            assert!(matches!(
+                thumbnail::<4>(&mut buf.as_slice(), u32::MAX, filter),
+                Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof
+            ));
+        }
+    }
+}
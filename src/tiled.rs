@@ -0,0 +1,429 @@
+//! Tiled images for random access
+//!
+//! A tiled image is stored as a grid of independently encoded QOI images, so that any region of
+//! it can be decoded by only reading the tiles overlapping it. Its layout is, with all integers in
+//! big-endian:
+//!
+//! | Field                          | Size in bytes  |
+//! |--------------------------------|----------------|
+//! | Image header                   | 14             |
+//! | `tile_width`, `tile_height`    | 4 + 4          |
+//! | Tile index                     | 12 per tile    |
+//! | Tiles                          |                |
+//!
+//! The image header is the same as the header of a QOI image with the whole dimensions, apart from
+//! its magic bytes `b"qoit"`. Tiles are stored in row-major order, where tiles at the right and
+//! bottom edges are cut short to fit the image. Each entry of the tile index holds the offset of a
+//! tile from the start of the tiled image as a `u64` and its size as a `u32`.
+
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::{
+    constants::QOI_MAGIC,
+    decode::decode,
+    encode::header,
+    header::{ColorSpace, Header},
+    io::{Reader, Writer},
+    pixel::{Pixel, SupportedChannels},
+    Error, Result,
+};
+
+/// Magic bytes of a tiled image
+const TILED_MAGIC: &[u8; 4] = b"qoit";
+
+/// Size of a single entry of the tile index in bytes
+const INDEX_ENTRY_SIZE: usize = 12;
+
+/// Maximum number of tile index entries allocated up front, before any of them are read
+const MAX_PREALLOCATED_TILES: usize = 1 << 16;
+
+/// Encodes `pixels` as a tiled image of `tile_width` by `tile_height` tiles, each encoded with
+/// [`encode`](crate::encode), then writing it into the provided `writer`.
+///
+/// The function returns the number of bytes written to the `writer`.
+///
+/// # Errors
+/// This function returns `Err` in one of the following cases:
+///
+/// 1. Either [`Writer::write_byte`] or [`Writer::write_from_slice`] fails.
+/// 2. The provided `width` and `height` differs from the length of `pixels`
+///    ([`Error::UnmatchedDataSize`])
+/// 3. Either `tile_width` or `tile_height` is `0`, or a tile encodes to more than `u32::MAX` bytes
+///    ([`Error::InvalidTileSize`])
+pub fn encode<const N: usize>(
+    writer: &mut impl Writer,
+    pixels: &[Pixel<N>],
+    width: u32,
+    height: u32,
+    color_space: ColorSpace,
+    tile_width: u32,
+    tile_height: u32,
+) -> Result<usize>
+where
+    Pixel<N>: SupportedChannels,
+{
+    // Ensure size of image data provided is the same as the provided dimensions
+    let image_size = (width as usize).saturating_mul(height as usize);
+    if pixels.len() != image_size {
+        return Err(Error::UnmatchedDataSize {
+            data_size: pixels.len(),
+            header_size: image_size,
+        });
+    }
+
+    if tile_width == 0 || tile_height == 0 {
+        return Err(Error::InvalidTileSize {
+            width: tile_width,
+            height: tile_height,
+        });
+    }
+
+    // Encode every tile
+    let mut tiles = vec![];
+    for tile_y in (0..height).step_by(tile_height as usize) {
+        for tile_x in (0..width).step_by(tile_width as usize) {
+            let tile_width = tile_width.min(width - tile_x);
+            let tile_height = tile_height.min(height - tile_y);
+
+            let tile_pixels: Vec<_> = (tile_y..tile_y + tile_height)
+                .flat_map(|y| {
+                    let start = y as usize * width as usize + tile_x as usize;
+                    &pixels[start..start + tile_width as usize]
+                })
+                .copied()
+                .collect();
+
+            let mut buf = vec![];
+            crate::encode(&mut buf, &tile_pixels, tile_width, tile_height, color_space)?;
+            tiles.push(buf);
+        }
+    }
+
+    let mut written = 0;
+
+    // Write header information
+    {
+        let mut bytes = header::<N>(width, height, color_space).as_bytes();
+        bytes[0..4].copy_from_slice(TILED_MAGIC);
+
+        written += writer.write_from_slice(&bytes)?;
+        written += writer.write_from_slice(&tile_width.to_be_bytes())?;
+        written += writer.write_from_slice(&tile_height.to_be_bytes())?;
+    }
+
+    // Write the tile index
+    let mut offset = (Header::SIZE + 8 + tiles.len() * INDEX_ENTRY_SIZE) as u64;
+    for tile in &tiles {
+        let size = u32::try_from(tile.len()).map_err(|_| Error::InvalidTileSize {
+            width: tile_width,
+            height: tile_height,
+        })?;
+
+        written += writer.write_from_slice(&offset.to_be_bytes())?;
+        written += writer.write_from_slice(&size.to_be_bytes())?;
+
+        offset += tile.len() as u64;
+    }
+
+    for tile in &tiles {
+        written += writer.write_from_slice(tile)?;
+    }
+
+    Ok(written)
+}
+
+/// A tiled image opened for decoding regions of it
+#[derive(Debug)]
+pub struct TiledImage<R> {
+    reader: R,
+
+    /// Position of the start of the tiled image in the `reader`
+    start: u64,
+
+    header: Header,
+    tile_width: u32,
+    tile_height: u32,
+
+    /// Offset and size of each tile
+    tiles: Vec<(u64, u32)>,
+}
+
+impl<R: Read + Seek> TiledImage<R> {
+    /// Reads the header and tile index of a tiled image starting at the current position of the
+    /// `reader`. No tile is read until a region is decoded.
+    ///
+    /// # Errors
+    /// This function returns `Err` if the `reader` fails, including when the data ends early, or
+    /// the header is invalid (see [`Error::InvalidMagic`], [`Error::InvalidChannelNumber`],
+    /// [`Error::InvalidColorSpace`] and [`Error::InvalidTileSize`]).
+    pub fn open(mut reader: R) -> Result<Self> {
+        let start = reader.stream_position().map_err(Error::IoError)?;
+
+        // Read header information
+        let header = {
+            let mut bytes = [0; Header::SIZE];
+            reader.read_to_slice(&mut bytes)?;
+
+            if &bytes[0..4] != TILED_MAGIC {
+                return Err(Error::InvalidMagic(bytes[0..4].try_into().unwrap()));
+            }
+
+            // NB: Apart from the magic bytes, the header is the same as a QOI header
+            bytes[0..4].copy_from_slice(QOI_MAGIC);
+            Header::from_bytes(bytes)?
+        };
+
+        let tile_width = read_u32(&mut reader)?;
+        let tile_height = read_u32(&mut reader)?;
+        if tile_width == 0 || tile_height == 0 {
+            return Err(Error::InvalidTileSize {
+                width: tile_width,
+                height: tile_height,
+            });
+        }
+
+        // Read the tile index
+        let tile_count = (header.width().div_ceil(tile_width) as usize)
+            .saturating_mul(header.height().div_ceil(tile_height) as usize);
+
+        // NB: The tile count comes from the header alone, so it is not trusted with the allocation
+        let mut tiles = Vec::with_capacity(tile_count.min(MAX_PREALLOCATED_TILES));
+        for _ in 0..tile_count {
+            tiles.push((read_u64(&mut reader)?, read_u32(&mut reader)?));
+        }
+
+        Ok(Self {
+            reader,
+            start,
+            header,
+            tile_width,
+            tile_height,
+            tiles,
+        })
+    }
+
+    /// Header of the whole image
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Dimensions of the tiles, apart from those cut short at the right and bottom edges
+    pub fn tile_size(&self) -> (u32, u32) {
+        (self.tile_width, self.tile_height)
+    }
+
+    /// Decodes the region of `width` and `height` at (`x`, `y`), only reading the tiles
+    /// overlapping it. Pixels are returned in row-major order, converted as by
+    /// [`decode`](crate::decode).
+    ///
+    /// # Errors
+    /// This function returns `Err` in one of the following cases:
+    ///
+    /// 1. The region is not entirely within the image ([`Error::InvalidRegion`])
+    /// 2. Reading or decoding any of the overlapping tiles fails, see [`decode`](crate::decode)
+    /// 3. The dimensions of a tile do not match the tile index ([`Error::UnmatchedDataSize`])
+    pub fn decode_region<const N: usize>(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<Pixel<N>>>
+    where
+        Pixel<N>: SupportedChannels,
+    {
+        let within = |start: u32, length: u32, size: u32| {
+            start.checked_add(length).is_some_and(|end| end <= size)
+        };
+        if !within(x, width, self.header.width()) || !within(y, height, self.header.height()) {
+            return Err(Error::InvalidRegion {
+                x,
+                y,
+                width,
+                height,
+            });
+        }
+
+        let mut pixels = vec![Pixel::<N>::default(); width as usize * height as usize];
+        if pixels.is_empty() {
+            return Ok(pixels);
+        }
+
+        let columns = self.header.width().div_ceil(self.tile_width);
+
+        for row in y / self.tile_height..=(y + height - 1) / self.tile_height {
+            for column in x / self.tile_width..=(x + width - 1) / self.tile_width {
+                let (tile_x, tile_y) = (column * self.tile_width, row * self.tile_height);
+                let tile_width = self.tile_width.min(self.header.width() - tile_x);
+                let tile_height = self.tile_height.min(self.header.height() - tile_y);
+
+                let (offset, size) = self.tiles[row as usize * columns as usize + column as usize];
+                self.reader
+                    .seek(SeekFrom::Start(self.start.saturating_add(offset)))
+                    .map_err(Error::IoError)?;
+                let (header, tile) = decode::<N>(&mut (&mut self.reader).take(size as u64))?;
+
+                if (header.width(), header.height()) != (tile_width, tile_height) {
+                    return Err(Error::UnmatchedDataSize {
+                        data_size: tile.len(),
+                        header_size: tile_width as usize * tile_height as usize,
+                    });
+                }
+
+                // Copy the rows of the tile within the region
+                let (start_x, end_x) = (x.max(tile_x), (x + width).min(tile_x + tile_width));
+                let (start_y, end_y) = (y.max(tile_y), (y + height).min(tile_y + tile_height));
+
+                for pixel_y in start_y..end_y {
+                    let source = (pixel_y - tile_y) as usize * tile_width as usize
+                        + (start_x - tile_x) as usize;
+                    let target = (pixel_y - y) as usize * width as usize + (start_x - x) as usize;
+                    let length = (end_x - start_x) as usize;
+
+                    pixels[target..target + length].copy_from_slice(&tile[source..source + length]);
+                }
+            }
+        }
+
+        Ok(pixels)
+    }
+}
+
+fn read_u32(reader: &mut impl Reader) -> Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_to_slice(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Reader) -> Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_to_slice(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn image(width: u32, height: u32) -> Vec<Pixel<4>> {
+        (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                Pixel::rgba(x as u8, y as u8, (x * y) as u8, 255 - (x / 4) as u8)
+            })
+            .collect()
+    }
+
+    fn region(
+        pixels: &[Pixel<4>],
+        image_width: u32,
+        x: u32,
+        y: u32,
+        w: u32,
+        h: u32,
+    ) -> Vec<Pixel<4>> {
+        (y..y + h)
+            .flat_map(|row| {
+                let start = (row * image_width + x) as usize;
+                &pixels[start..start + w as usize]
+            })
+            .copied()
+            .collect()
+    }
+
+    #[test]
+    fn decodes_any_region() {
+        let (width, height) = (37, 29);
+        let pixels = image(width, height);
+
+        let mut buf = vec![];
+        let written = encode(&mut buf, &pixels, width, height, ColorSpace::Srgb, 8, 5).unwrap();
+        assert_eq!(written, buf.len());
+
+        let mut tiled = TiledImage::open(Cursor::new(&buf)).unwrap();
+        assert_eq!(
+            (tiled.header().width(), tiled.header().height()),
+            (width, height)
+        );
+        assert_eq!(tiled.tile_size(), (8, 5));
+
+        for (x, y, w, h) in [
+            (0, 0, width, height),
+            (0, 0, 1, 1),
+            (7, 4, 2, 2),
+            (8, 5, 8, 5),
+            (3, 11, 30, 7),
+            (36, 28, 1, 1),
+            (10, 10, 0, 0),
+        ] {
+            assert_eq!(
+                tiled.decode_region::<4>(x, y, w, h).unwrap(),
+                region(&pixels, width, x, y, w, h),
+                "region ({x}, {y}, {w}, {h})"
+            );
+        }
+    }
+
+    #[test]
+    fn only_reads_overlapping_tiles() {
+        let (width, height) = (16, 16);
+        let pixels = image(width, height);
+
+        let mut buf = vec![];
+        encode(&mut buf, &pixels, width, height, ColorSpace::Srgb, 8, 8).unwrap();
+
+        // NB: Corrupt the end marker of the last tile, which the top-left region does not overlap
+        let end = buf.len();
+        buf[end - 1] = 0xff;
+
+        let mut tiled = TiledImage::open(Cursor::new(&buf)).unwrap();
+        assert_eq!(
+            tiled.decode_region::<4>(0, 0, 8, 16).unwrap(),
+            region(&pixels, width, 0, 0, 8, 16)
+        );
+        assert!(matches!(
+            tiled.decode_region::<4>(8, 8, 1, 1),
+            Err(Error::InvalidEndMarker(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_region() {
+        let pixels = image(4, 4);
+
+        let mut buf = vec![];
+        encode(&mut buf, &pixels, 4, 4, ColorSpace::Srgb, 2, 2).unwrap();
+
+        let mut tiled = TiledImage::open(Cursor::new(&buf)).unwrap();
+        assert!(matches!(
+            tiled.decode_region::<4>(3, 0, 2, 1),
+            Err(Error::InvalidRegion { x: 3, .. })
+        ));
+        assert!(matches!(
+            tiled.decode_region::<4>(0, u32::MAX, 1, 2),
+            Err(Error::InvalidRegion { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_container() {
+        let pixels = image(4, 4);
+
+        let mut buf = vec![];
+        crate::encode(&mut buf, &pixels, 4, 4, ColorSpace::Srgb).unwrap();
+        assert!(matches!(
+            TiledImage::open(Cursor::new(&buf)),
+            Err(Error::InvalidMagic(magic)) if &magic == QOI_MAGIC
+        ));
+
+        assert!(matches!(
+            encode(&mut vec![], &pixels, 4, 4, ColorSpace::Srgb, 0, 2),
+            Err(Error::InvalidTileSize {
+                width: 0,
+                height: 2
+            })
+        ));
+    }
+}
@@ -0,0 +1,233 @@
+//! Reversible colour transforms applied to pixels before encoding
+//!
+//! In photographic content, the three colour channels of neighbouring pixels tend to change
+//! together, so their differences rarely fit the small ranges of `QOI_OP_DIFF` and `QOI_OP_LUMA`.
+//! A [`Transform`] decorrelates the channels of each pixel before the `QOI_OP`s are picked, and the
+//! decoder inverts it on every decoded pixel. Both transforms work modulo 256, so they are lossless
+//! for any pixel, and leave alpha untouched.
+//!
+//! NB: `QOI_OP_LUMA` already stores red and blue relative to green, so neither transform is a
+//! NB: clear win. On the benchmark corpus (`cargo bench --bench transform`), subtract-green is never
+//! NB: smaller and grows photos by a fifth, while YCoCg-R shaves 1% off noise but adds 1-2% to
+//! NB: photos, gradients and sprites. YCoCg-R pays off where colours shift in hue at constant
+//! NB: brightness, turning `QOI_OP_RGB`s into `QOI_OP_LUMA`s.
+//!
+//! Images of the [`Profile::Extended`](crate::Profile::Extended) profile store their transform in
+//! the upper four bits of the channels byte of the header:
+//!
+//! | Value | Transform                    | Stored `(r, g, b)`                          |
+//! |-------|------------------------------|---------------------------------------------|
+//! | `0`   | [`Transform::Identity`]      | `(r, g, b)`                                 |
+//! | `1`   | [`Transform::SubtractGreen`] | `(r - g, g, b - g)`                         |
+//! | `2`   | [`Transform::YCoCgR`]        | `(Co, Y, Cg)`, see [`Transform::YCoCgR`]    |
+
+use crate::pixel::{Pixel, SupportedChannels};
+
+/// Lossless transform of the colour channels of each pixel
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Transform {
+    /// Pixels are stored as is, as in the specification
+    #[default]
+    Identity,
+
+    /// Red and blue are stored relative to green, as in WebP lossless
+    SubtractGreen,
+
+    /// The lifting form of YCoCg-R, with luma stored in green and the chroma channels in red and
+    /// blue:
+    ///
+    /// ```text
+    /// Co = r - b
+    /// t  = b + (Co >> 1)
+    /// Cg = g - t
+    /// Y  = t + (Cg >> 1)
+    /// ```
+    ///
+    /// where `>>` shifts the channel as a signed byte.
+    YCoCgR,
+}
+
+impl Transform {
+    /// Transforms that can be stored in an image
+    const STORED: [Transform; 3] = [
+        Transform::Identity,
+        Transform::SubtractGreen,
+        Transform::YCoCgR,
+    ];
+
+    /// Value stored in the header, see the [module docs](self)
+    pub(crate) fn to_u8(self) -> u8 {
+        match self {
+            Transform::Identity => 0,
+            Transform::SubtractGreen => 1,
+            Transform::YCoCgR => 2,
+        }
+    }
+
+    pub(crate) fn from_u8(value: u8) -> Option<Self> {
+        Self::STORED.get(value as usize).copied()
+    }
+
+    /// Transforms a `pixel` into the channels to encode
+    pub(crate) fn forward<const N: usize>(self, pixel: Pixel<N>) -> Pixel<N>
+    where
+        Pixel<N>: SupportedChannels,
+    {
+        let [r, g, b, a] = pixel.as_inner_rgba();
+
+        let rgb = match self {
+            Transform::Identity => return pixel,
+            Transform::SubtractGreen => [r.wrapping_sub(g), g, b.wrapping_sub(g)],
+            Transform::YCoCgR => {
+                let co = r.wrapping_sub(b);
+                let t = b.wrapping_add(half(co));
+                let cg = g.wrapping_sub(t);
+                let y = t.wrapping_add(half(cg));
+
+                [co, y, cg]
+            }
+        };
+
+        Pixel::from_inner_rgba([rgb[0], rgb[1], rgb[2], a])
+    }
+
+    /// Inverts [`Transform::forward`] on a decoded `pixel`
+    pub(crate) fn inverse<const N: usize>(self, pixel: Pixel<N>) -> Pixel<N>
+    where
+        Pixel<N>: SupportedChannels,
+    {
+        let [r, g, b, a] = pixel.as_inner_rgba();
+
+        let rgb = match self {
+            Transform::Identity => return pixel,
+            Transform::SubtractGreen => [r.wrapping_add(g), g, b.wrapping_add(g)],
+            Transform::YCoCgR => {
+                let (co, y, cg) = (r, g, b);
+
+                // NB: Each lifting step is undone in reverse, with the same halves as `forward`
+                let t = y.wrapping_sub(half(cg));
+                let g = cg.wrapping_add(t);
+                let b = t.wrapping_sub(half(co));
+                let r = b.wrapping_add(co);
+
+                [r, g, b]
+            }
+        };
+
+        Pixel::from_inner_rgba([rgb[0], rgb[1], rgb[2], a])
+    }
+}
+
+/// Halves a channel holding a signed difference, rounding towards negative infinity
+fn half(value: u8) -> u8 {
+    ((value as i8) >> 1) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode, encode_with_options, ColorSpace, EncodeOptions, Error, Profile};
+
+    #[test]
+    fn inverts_every_pixel() {
+        for transform in Transform::STORED {
+            for r in 0..=255 {
+                for g in (0..=255).step_by(3) {
+                    for b in 0..=255 {
+                        let pixel = Pixel::rgba(r, g, b, r ^ b);
+
+                        assert_eq!(
+                            transform.inverse(transform.forward(pixel)),
+                            pixel,
+                            "{transform:?}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// A ramp in red alone, whose steps are too large for `QOI_OP_LUMA` in RGB
+    fn red_ramp(width: u32, height: u32) -> Vec<Pixel<3>> {
+        (0..width * height)
+            .map(|i| Pixel::rgb((i % width * 10) as u8, 100, 150))
+            .collect()
+    }
+
+    fn encode_with(pixels: &[Pixel<3>], width: u32, height: u32, transform: Transform) -> Vec<u8> {
+        let options = EncodeOptions {
+            profile: Profile::Extended,
+            transform,
+            ..Default::default()
+        };
+
+        let mut buf = vec![];
+        encode_with_options(&mut buf, pixels, width, height, ColorSpace::Srgb, &options).unwrap();
+        buf
+    }
+
+    #[test]
+    fn round_trips_in_every_transform() {
+        let (width, height) = (20, 8);
+        let pixels = red_ramp(width, height);
+
+        for transform in Transform::STORED {
+            let buf = encode_with(&pixels, width, height, transform);
+
+            let (header, decoded) = decode::<3>(&mut buf.as_slice()).unwrap();
+            assert_eq!(header.channels() as u8, 3);
+            assert_eq!(decoded, pixels, "{transform:?}");
+        }
+    }
+
+    #[test]
+    fn ycocg_shrinks_changes_in_chroma() {
+        let (width, height) = (20, 8);
+        let pixels = red_ramp(width, height);
+
+        // NB: Every step of `+10` red becomes `(+10, +2, -5)` in `(Co, Y, Cg)`, which fits
+        // NB: `QOI_OP_LUMA` instead of `QOI_OP_RGB`
+        let identity = encode_with(&pixels, width, height, Transform::Identity);
+        let ycocg = encode_with(&pixels, width, height, Transform::YCoCgR);
+
+        assert!(
+            ycocg.len() < identity.len(),
+            "{} >= {}",
+            ycocg.len(),
+            identity.len()
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_transform() {
+        let options = EncodeOptions {
+            profile: Profile::Extended,
+            transform: Transform::YCoCgR,
+            ..Default::default()
+        };
+
+        let mut buf = vec![];
+        encode_with_options(&mut buf, &red_ramp(1, 1), 1, 1, ColorSpace::Srgb, &options).unwrap();
+        assert_eq!(buf[12], 0x23);
+        buf[12] = 0x33;
+
+        assert!(matches!(
+            decode::<3>(&mut buf.as_slice()),
+            Err(Error::InvalidTransform(0x33))
+        ));
+    }
+
+    #[test]
+    fn standard_profile_ignores_transform() {
+        let options = EncodeOptions {
+            transform: Transform::SubtractGreen,
+            ..Default::default()
+        };
+
+        let mut buf = vec![];
+        encode_with_options(&mut buf, &red_ramp(2, 1), 2, 1, ColorSpace::Srgb, &options).unwrap();
+
+        assert_eq!(buf[12], 3);
+        assert_eq!(decode::<3>(&mut buf.as_slice()).unwrap().1, red_ramp(2, 1));
+    }
+}
@@ -0,0 +1,334 @@
+//! Orders in which the pixels of an image are visited while encoding
+//!
+//! QOI encodes pixels row by row, so an image whose colours change faster along rows than along
+//! columns compresses poorly. Images of the [`Profile::Extended`](crate::Profile::Extended)
+//! profile may instead visit their pixels in another [`Traversal`], which is stored in the upper
+//! four bits of the color space byte of the header:
+//!
+//! | Value | Traversal                   |
+//! |-------|-----------------------------|
+//! | `0`   | [`Traversal::RowMajor`]     |
+//! | `1`   | [`Traversal::ColumnMajor`]  |
+//! | `2`   | [`Traversal::Serpentine`]   |
+//! | `3`   | [`Traversal::Hilbert`]      |
+//!
+//! Only the order of the `QOI_OP`s changes: decoded pixels are always returned row by row.
+
+/// Order in which the pixels of an image are encoded
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Traversal {
+    /// Row by row, each from left to right, as in the specification
+    #[default]
+    RowMajor,
+
+    /// Column by column, each from top to bottom
+    ColumnMajor,
+
+    /// Row by row, alternating between left to right and right to left, so that consecutive
+    /// pixels are always adjacent
+    Serpentine,
+
+    /// Along a Hilbert curve generalised to any width and height, which keeps consecutive pixels
+    /// close in both directions
+    Hilbert,
+
+    /// Encodes with each of the other traversals and keeps the smallest output. Only valid when
+    /// encoding, as the chosen traversal is what gets stored.
+    Auto,
+}
+
+impl Traversal {
+    /// Traversals that can be stored in an image
+    pub(crate) const STORED: [Traversal; 4] = [
+        Traversal::RowMajor,
+        Traversal::ColumnMajor,
+        Traversal::Serpentine,
+        Traversal::Hilbert,
+    ];
+
+    /// Value stored in the header, see the [module docs](self)
+    pub(crate) fn to_u8(self) -> u8 {
+        match self {
+            Traversal::RowMajor | Traversal::Auto => 0,
+            Traversal::ColumnMajor => 1,
+            Traversal::Serpentine => 2,
+            Traversal::Hilbert => 3,
+        }
+    }
+
+    pub(crate) fn from_u8(value: u8) -> Option<Self> {
+        Self::STORED.get(value as usize).copied()
+    }
+
+    /// Row-major indices of the pixels of a `width` by `height` image, in the order this traversal
+    /// visits them
+    pub(crate) fn order(self, width: u32, height: u32) -> Vec<usize> {
+        let (width, height) = (width as usize, height as usize);
+
+        match self {
+            Traversal::RowMajor | Traversal::Auto => (0..width * height).collect(),
+            Traversal::ColumnMajor => (0..width)
+                .flat_map(|x| (0..height).map(move |y| y * width + x))
+                .collect(),
+            Traversal::Serpentine => (0..height)
+                .flat_map(|y| {
+                    (0..width).map(move |x| match y % 2 {
+                        0 => y * width + x,
+                        _ => y * width + width - 1 - x,
+                    })
+                })
+                .collect(),
+            Traversal::Hilbert if width == 0 || height == 0 => vec![],
+            Traversal::Hilbert => {
+                let mut order = Vec::with_capacity(width * height);
+                let (width, height) = (width as i64, height as i64);
+
+                // NB: The curve always runs along the longer side first
+                match width >= height {
+                    true => hilbert(&mut order, width, (0, 0), (width, 0), (0, height)),
+                    false => hilbert(&mut order, width, (0, 0), (0, height), (width, 0)),
+                }
+
+                order
+            }
+        }
+    }
+
+    /// Reorders `pixels` from row-major into the order of this traversal
+    pub(crate) fn permute<T: Copy>(self, pixels: &[T], width: u32, height: u32) -> Vec<T> {
+        self.order(width, height)
+            .into_iter()
+            .map(|i| pixels[i])
+            .collect()
+    }
+
+    /// Reorders `pixels` from the order of this traversal back into row-major
+    pub(crate) fn unpermute<T: Copy>(self, pixels: &mut [T], width: u32, height: u32) {
+        if matches!(self, Traversal::RowMajor | Traversal::Auto) {
+            return;
+        }
+
+        let traversed = pixels.to_vec();
+        for (pixel, i) in traversed.into_iter().zip(self.order(width, height)) {
+            pixels[i] = pixel;
+        }
+    }
+}
+
+/// Appends the row-major indices of the rectangle at `(x, y)`, spanned by the major axis `a` and
+/// the minor axis `b`, in the order of a generalised Hilbert curve ("gilbert") running along `a`.
+///
+/// NB: Every step of the curve moves to an adjacent pixel, except for a single diagonal step in
+/// NB: some rectangles with an odd side, which cannot be avoided.
+fn hilbert(
+    order: &mut Vec<usize>,
+    stride: i64,
+    (x, y): (i64, i64),
+    (ax, ay): (i64, i64),
+    (bx, by): (i64, i64),
+) {
+    let w = (ax + ay).abs();
+    let h = (bx + by).abs();
+    let (dax, day) = (ax.signum(), ay.signum());
+    let (dbx, dby) = (bx.signum(), by.signum());
+
+    if h == 1 || w == 1 {
+        let (length, dx, dy) = match h {
+            1 => (w, dax, day),
+            _ => (h, dbx, dby),
+        };
+        order.extend((0..length).map(|i| ((y + i * dy) * stride + x + i * dx) as usize));
+        return;
+    }
+
+    let (mut ax2, mut ay2) = (ax.div_euclid(2), ay.div_euclid(2));
+    let (mut bx2, mut by2) = (bx.div_euclid(2), by.div_euclid(2));
+    let w2 = (ax2 + ay2).abs();
+    let h2 = (bx2 + by2).abs();
+
+    if 2 * w > 3 * h {
+        // NB: Split the long side in two, keeping the first half even so the curve ends next to
+        // NB: the second half
+        if w2 % 2 == 1 && w > 2 {
+            (ax2, ay2) = (ax2 + dax, ay2 + day);
+        }
+
+        hilbert(order, stride, (x, y), (ax2, ay2), (bx, by));
+        hilbert(
+            order,
+            stride,
+            (x + ax2, y + ay2),
+            (ax - ax2, ay - ay2),
+            (bx, by),
+        );
+    } else {
+        // NB: Go up the first half of the short side, across, and back down
+        if h2 % 2 == 1 && h > 2 {
+            (bx2, by2) = (bx2 + dbx, by2 + dby);
+        }
+
+        hilbert(order, stride, (x, y), (bx2, by2), (ax2, ay2));
+        hilbert(
+            order,
+            stride,
+            (x + bx2, y + by2),
+            (ax, ay),
+            (bx - bx2, by - by2),
+        );
+        hilbert(
+            order,
+            stride,
+            (x + (ax - dax) + (bx2 - dbx), y + (ay - day) + (by2 - dby)),
+            (-bx2, -by2),
+            (-(ax - ax2), -(ay - ay2)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode, encode_with_options, ColorSpace, EncodeOptions, Error, Pixel, Profile};
+
+    const SIZES: [(u32, u32); 9] = [
+        (0, 5),
+        (1, 1),
+        (1, 7),
+        (7, 1),
+        (2, 2),
+        (16, 16),
+        (5, 3),
+        (3, 8),
+        (33, 17),
+    ];
+
+    #[test]
+    fn orders_are_permutations() {
+        for (width, height) in SIZES {
+            for traversal in Traversal::STORED {
+                let mut order = traversal.order(width, height);
+                order.sort_unstable();
+
+                assert!(
+                    order.iter().copied().eq(0..(width * height) as usize),
+                    "{traversal:?} {width}x{height}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn hilbert_steps_to_adjacent_pixels() {
+        for size in [2, 4, 16, 64] {
+            let order = Traversal::Hilbert.order(size, size);
+
+            for step in order.windows(2) {
+                let (from, to) = (step[0] as i64, step[1] as i64);
+                let (dx, dy) = (
+                    to % size as i64 - from % size as i64,
+                    to / size as i64 - from / size as i64,
+                );
+                assert_eq!(dx.abs() + dy.abs(), 1, "{size}x{size}");
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_in_every_traversal() {
+        let (width, height) = (33, 17);
+        let pixels: Vec<_> = (0..width * height)
+            .map(|i| Pixel::rgba((i % width) as u8 * 7, (i / width) as u8 * 13, i as u8, 255))
+            .collect();
+
+        for traversal in Traversal::STORED.into_iter().chain([Traversal::Auto]) {
+            let options = EncodeOptions {
+                profile: Profile::Extended,
+                traversal,
+                ..Default::default()
+            };
+
+            let mut buf = vec![];
+            encode_with_options(&mut buf, &pixels, width, height, ColorSpace::Srgb, &options)
+                .unwrap();
+
+            let (header, decoded) = decode::<4>(&mut buf.as_slice()).unwrap();
+            assert_eq!(header.color_space(), ColorSpace::Srgb);
+            assert_eq!(decoded, pixels, "{traversal:?}");
+        }
+    }
+
+    #[test]
+    fn auto_keeps_the_smallest() {
+        // NB: Vertical stripes, which only runs of a column-major traversal cover
+        let (width, height) = (64, 64);
+        let pixels: Vec<_> = (0..width * height)
+            .map(|i| {
+                let x = (i % width) as u8;
+                Pixel::rgb(x.wrapping_mul(71), x.wrapping_mul(29), x ^ 0x5a)
+            })
+            .collect();
+
+        let encoded_size = |traversal| {
+            let options = EncodeOptions {
+                profile: Profile::Extended,
+                traversal,
+                ..Default::default()
+            };
+
+            let mut buf = vec![];
+            encode_with_options(&mut buf, &pixels, width, height, ColorSpace::Srgb, &options)
+                .unwrap();
+            buf.len()
+        };
+
+        let auto = Traversal::STORED
+            .map(encoded_size)
+            .into_iter()
+            .min()
+            .unwrap();
+        assert_eq!(encoded_size(Traversal::Auto), auto);
+        assert_eq!(auto, encoded_size(Traversal::ColumnMajor));
+        assert!(auto * 4 < encoded_size(Traversal::RowMajor));
+    }
+
+    #[test]
+    fn rejects_unknown_traversal() {
+        let options = EncodeOptions {
+            profile: Profile::Extended,
+            traversal: Traversal::Hilbert,
+            ..Default::default()
+        };
+
+        let mut buf = vec![];
+        encode_with_options(
+            &mut buf,
+            &[Pixel::rgb(1, 2, 3)],
+            1,
+            1,
+            ColorSpace::Srgb,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(buf[13], 0x30);
+        buf[13] = 0x40;
+
+        assert!(matches!(
+            decode::<3>(&mut buf.as_slice()),
+            Err(Error::InvalidTraversal(0x40))
+        ));
+    }
+
+    #[test]
+    fn standard_profile_ignores_traversal() {
+        let pixels = [Pixel::rgb(1, 2, 3), Pixel::rgb(4, 5, 6)];
+        let options = EncodeOptions {
+            traversal: Traversal::ColumnMajor,
+            ..Default::default()
+        };
+
+        let mut buf = vec![];
+        encode_with_options(&mut buf, &pixels, 1, 2, ColorSpace::AllLinear, &options).unwrap();
+
+        assert_eq!(buf[13], ColorSpace::AllLinear as u8);
+    }
+}